@@ -0,0 +1,150 @@
+use super::blocking_task::{BlockingTask, Spawner};
+use super::error::Error;
+use super::git_object::GitObject;
+use super::object_format::ObjectId;
+use super::path_fragment::PathFragment;
+use super::shared_bundled_repository::SharedBundledRepository;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+
+// Async facade over a shared bundle. Every call runs the blocking operation
+// off the caller's executor, on the default pool unless a spawner is given;
+// `blocking()` still exposes the synchronous API.
+#[derive(Clone)]
+pub struct AsyncBundledRepository {
+  shared: SharedBundledRepository,
+  spawner: Option<Arc<dyn Spawner>>,
+}
+
+impl AsyncBundledRepository {
+  pub fn new(shared: SharedBundledRepository) -> AsyncBundledRepository {
+    AsyncBundledRepository {
+      shared,
+      spawner: None,
+    }
+  }
+
+  pub fn with_spawner(
+    shared: SharedBundledRepository,
+    spawner: Arc<dyn Spawner>,
+  ) -> AsyncBundledRepository {
+    AsyncBundledRepository {
+      shared,
+      spawner: Some(spawner),
+    }
+  }
+
+  pub fn blocking(&self) -> &SharedBundledRepository {
+    &self.shared
+  }
+
+  pub fn find(&self, oid: ObjectId) -> BlockingTask<Option<Arc<GitObject>>> {
+    let shared = self.shared.clone();
+
+    self.spawn(move || shared.find(&oid))
+  }
+
+  pub fn exists(&self, oid: ObjectId) -> BlockingTask<bool> {
+    let shared = self.shared.clone();
+
+    self.spawn(move || shared.exists(&oid))
+  }
+
+  pub fn insert(&self, object: GitObject) -> BlockingTask<ObjectId> {
+    let shared = self.shared.clone();
+
+    self.spawn(move || shared.insert(&object))
+  }
+
+  pub fn refs(&self, fragment: PathFragment) -> BlockingTask<Option<BTreeMap<String, ObjectId>>> {
+    let shared = self.shared.clone();
+
+    self.spawn(move || Ok(shared.read()?.refs(&fragment)))
+  }
+
+  pub fn set_refs(
+    &self,
+    fragment: PathFragment,
+    refs: BTreeMap<String, ObjectId>,
+  ) -> BlockingTask<()> {
+    let shared = self.shared.clone();
+
+    self.spawn(move || shared.write()?.set_refs(&fragment, refs))
+  }
+
+  fn spawn<T, F>(&self, f: F) -> BlockingTask<T>
+  where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, Error> + Send + 'static,
+  {
+    match &self.spawner {
+      Some(spawner) => BlockingTask::spawn_on(&**spawner, f),
+      None => BlockingTask::spawn(f),
+    }
+  }
+}
+
+impl fmt::Debug for AsyncBundledRepository {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "AsyncBundledRepository")
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::blocking_task::tests::block_on;
+  use crate::blocking_task::ThreadPool;
+  use crate::bundled_repository::BundledRepository;
+  use crate::object_format::ObjectFormat;
+  use crate::test_helper::blob;
+
+  fn repository() -> AsyncBundledRepository {
+    let shared = SharedBundledRepository::new(BundledRepository::new().unwrap());
+
+    AsyncBundledRepository::new(shared)
+  }
+
+  #[test]
+  fn test_insert_and_find() {
+    let repository = repository();
+    let oid = block_on(repository.insert(blob(b"hello"))).unwrap();
+
+    assert_eq!(block_on(repository.exists(oid)), Ok(true));
+    assert_eq!(
+      block_on(repository.find(oid)),
+      Ok(Some(Arc::new(blob(b"hello"))))
+    );
+    assert_eq!(
+      block_on(repository.find(ObjectId::zero(ObjectFormat::Sha1))),
+      Ok(None)
+    );
+    assert_eq!(
+      repository.blocking().find(&oid),
+      Ok(Some(Arc::new(blob(b"hello"))))
+    );
+  }
+
+  #[test]
+  fn test_with_spawner() {
+    let shared = SharedBundledRepository::new(BundledRepository::new().unwrap());
+    let repository =
+      AsyncBundledRepository::with_spawner(shared, Arc::new(ThreadPool::new(1).unwrap()));
+    let oid = block_on(repository.insert(blob(b"hello"))).unwrap();
+
+    assert_eq!(block_on(repository.exists(oid)), Ok(true));
+  }
+
+  #[test]
+  fn test_set_refs() {
+    let repository = repository();
+    let fragment = PathFragment::parse_full_qualified_fragment("github.com@a:repo").unwrap();
+    let oid = block_on(repository.insert(blob(b"hello"))).unwrap();
+    let mut refs = BTreeMap::new();
+    refs.insert("refs/heads/master".to_owned(), oid);
+    block_on(repository.set_refs(fragment.clone(), refs.clone())).unwrap();
+
+    assert_eq!(block_on(repository.refs(fragment)), Ok(Some(refs)));
+  }
+}
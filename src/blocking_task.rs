@@ -0,0 +1,274 @@
+use super::error::Error;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+pub type Job = Box<dyn FnOnce() + Send + 'static>;
+
+// Runs jobs handed over by `BlockingTask`. Lets callers put blocking work on
+// their runtime's own blocking pool instead of the default one.
+pub trait Spawner: Send + Sync {
+  fn spawn(&self, job: Job) -> Result<(), Error>;
+}
+
+// A fixed number of worker threads sharing one queue. Dropping the pool lets
+// the workers finish the queued jobs and exit.
+pub struct ThreadPool {
+  sender: Mutex<Sender<Job>>,
+}
+
+impl ThreadPool {
+  pub fn new(size: usize) -> Result<ThreadPool, Error> {
+    let (sender, receiver) = mpsc::channel::<Job>();
+    let receiver = Arc::new(Mutex::new(receiver));
+    for i in 0..size.max(1) {
+      let receiver = receiver.clone();
+      let result = thread::Builder::new()
+        .name(format!("ckusro-blocking-{}", i))
+        .spawn(move || work(&receiver));
+      if let Err(err) = result {
+        return Err(Error::TaskSpawnFailed {
+          detail: err.to_string(),
+        });
+      }
+    }
+
+    Ok(ThreadPool {
+      sender: Mutex::new(sender),
+    })
+  }
+}
+
+impl Spawner for ThreadPool {
+  fn spawn(&self, job: Job) -> Result<(), Error> {
+    let sender = match self.sender.lock() {
+      Ok(v) => Ok(v),
+      Err(_) => Err(Error::LockPoisoned),
+    }?;
+    match sender.send(job) {
+      Ok(_) => Ok(()),
+      Err(_) => Err(Error::TaskSpawnFailed {
+        detail: "worker threads exited".to_owned(),
+      }),
+    }
+  }
+}
+
+fn work(receiver: &Mutex<Receiver<Job>>) {
+  loop {
+    let job = match receiver.lock() {
+      Ok(v) => v.recv(),
+      Err(_) => return,
+    };
+    match job {
+      Ok(job) => job(),
+      // Every sender is gone, so the pool was dropped.
+      Err(_) => return,
+    }
+  }
+}
+
+static DEFAULT_POOL: Mutex<Option<Arc<ThreadPool>>> = Mutex::new(None);
+
+// Shared by every `BlockingTask::spawn`, sized to the available parallelism.
+// Created on first use; a failed creation is retried by the next call.
+pub fn default_spawner() -> Result<Arc<ThreadPool>, Error> {
+  let mut pool = match DEFAULT_POOL.lock() {
+    Ok(v) => v,
+    Err(err) => err.into_inner(),
+  };
+  if let Some(v) = pool.as_ref() {
+    return Ok(v.clone());
+  }
+
+  let size = thread::available_parallelism().map_or(4, |v| v.get());
+  let ret = Arc::new(ThreadPool::new(size)?);
+  *pool = Some(ret.clone());
+
+  Ok(ret)
+}
+
+struct State<T> {
+  result: Option<Result<T, Error>>,
+  waker: Option<Waker>,
+}
+
+// Runs blocking work on a spawner's thread and resolves once it finishes. It
+// does not depend on any executor, so it works under whichever runtime polls
+// it.
+pub struct BlockingTask<T> {
+  state: Arc<Mutex<State<T>>>,
+}
+
+impl<T: Send + 'static> BlockingTask<T> {
+  pub fn spawn<F>(f: F) -> BlockingTask<T>
+  where
+    F: FnOnce() -> Result<T, Error> + Send + 'static,
+  {
+    match default_spawner() {
+      Ok(spawner) => BlockingTask::spawn_on(&*spawner, f),
+      Err(err) => BlockingTask::ready(Err(err)),
+    }
+  }
+
+  // A spawner that fails resolves the task with its error.
+  pub fn spawn_on<F>(spawner: &dyn Spawner, f: F) -> BlockingTask<T>
+  where
+    F: FnOnce() -> Result<T, Error> + Send + 'static,
+  {
+    let ret = BlockingTask::ready_with(None);
+
+    let shared = ret.state.clone();
+    let job: Job = Box::new(move || {
+      let result = match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(v) => v,
+        Err(_) => Err(Error::TaskPanicked),
+      };
+
+      let mut state = lock(&shared);
+      state.result = Some(result);
+      if let Some(waker) = state.waker.take() {
+        waker.wake();
+      }
+    });
+    match spawner.spawn(job) {
+      Ok(_) => ret,
+      Err(err) => BlockingTask::ready(Err(err)),
+    }
+  }
+
+  fn ready(result: Result<T, Error>) -> BlockingTask<T> {
+    BlockingTask::ready_with(Some(result))
+  }
+
+  fn ready_with(result: Option<Result<T, Error>>) -> BlockingTask<T> {
+    BlockingTask {
+      state: Arc::new(Mutex::new(State {
+        result,
+        waker: None,
+      })),
+    }
+  }
+}
+
+impl<T> Future for BlockingTask<T> {
+  type Output = Result<T, Error>;
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    let mut state = lock(&self.state);
+    match state.result.take() {
+      Some(v) => Poll::Ready(v),
+      None => {
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+      }
+    }
+  }
+}
+
+// Only the two sides of one task share the state and neither panics while
+// holding it, so a poisoned lock can still be used.
+fn lock<T>(state: &Mutex<State<T>>) -> MutexGuard<'_, State<T>> {
+  match state.lock() {
+    Ok(v) => v,
+    Err(err) => err.into_inner(),
+  }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+  use super::*;
+  use std::task::Wake;
+  use std::thread::Thread;
+
+  struct ThreadWaker(Thread);
+
+  impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+      self.0.unpark();
+    }
+  }
+
+  pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = Box::pin(future);
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+
+    loop {
+      match future.as_mut().poll(&mut cx) {
+        Poll::Ready(v) => return v,
+        Poll::Pending => thread::park(),
+      }
+    }
+  }
+
+  mod blocking_task {
+    use super::*;
+
+    #[test]
+    fn test_spawn() {
+      let actual = block_on(BlockingTask::spawn(|| Ok(1 + 1)));
+
+      assert_eq!(actual, Ok(2));
+    }
+
+    #[test]
+    fn test_spawn_when_task_fails() {
+      let actual: Result<(), Error> = block_on(BlockingTask::spawn(|| Err(Error::InvalidHeader)));
+
+      assert_eq!(actual, Err(Error::InvalidHeader));
+    }
+
+    #[test]
+    fn test_spawn_when_task_panics() {
+      let actual: Result<(), Error> = block_on(BlockingTask::spawn(|| panic!("boom")));
+
+      assert_eq!(actual, Err(Error::TaskPanicked));
+    }
+
+    #[test]
+    fn test_spawn_on() {
+      let pool = ThreadPool::new(2).unwrap();
+      let tasks: Vec<BlockingTask<usize>> = (0..8)
+        .map(|i| BlockingTask::spawn_on(&pool, move || Ok(i * 2)))
+        .collect();
+      let actual: Vec<usize> = tasks.into_iter().map(|v| block_on(v).unwrap()).collect();
+
+      assert_eq!(actual, vec![0, 2, 4, 6, 8, 10, 12, 14]);
+    }
+
+    #[test]
+    fn test_spawn_on_when_spawner_fails() {
+      struct Failing;
+
+      impl Spawner for Failing {
+        fn spawn(&self, _: Job) -> Result<(), Error> {
+          Err(Error::TaskSpawnFailed {
+            detail: "full".to_owned(),
+          })
+        }
+      }
+
+      let actual: Result<(), Error> = block_on(BlockingTask::spawn_on(&Failing, || Ok(())));
+
+      assert_eq!(
+        actual,
+        Err(Error::TaskSpawnFailed {
+          detail: "full".to_owned()
+        })
+      );
+    }
+
+    #[test]
+    fn test_spawn_reuses_default_pool() {
+      let pool = default_spawner().unwrap();
+
+      assert!(Arc::ptr_eq(&pool, &default_spawner().unwrap()));
+      assert_eq!(block_on(BlockingTask::spawn(|| Ok(1))), Ok(1));
+    }
+  }
+}
@@ -1,33 +1,634 @@
+use super::commit::Commit;
+use super::dedup_report::DedupReport;
 use super::error::Error;
-use git2::Odb;
+use super::fsck_report::FsckReport;
+use super::git_directory::GitDirectory;
+use super::git_object::GitObject;
+use super::loose_object_storage::LooseObjectStorage;
+use super::namespace_ref_manager::NamespaceRefManager;
+use super::object_cache::{CacheStats, ObjectCache};
+use super::object_format::{ObjectFormat, ObjectId};
+use super::object_header::parse_hex_oid;
+use super::parse_mode::ParseMode;
+use super::path_fragment::PathFragment;
+use super::ref_transaction::{check_ref_name, RefTransaction};
+use super::storage::{io_result, MemoryStorage, Storage};
+use super::tag::Tag;
+use super::tree_object::{FileMode, TreeObject};
+use git2::ObjectType;
+use std::collections::{BTreeMap, HashSet};
 use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime};
 
-pub struct BundledRepository<'a> {
-  odb: Odb<'a>,
+const BUNDLE_VERSION: u32 = 1;
+const METADATA_FILE: &str = "metadata";
+const REFS_FILE: &str = "refs";
+const OBJECTS_DIR: &str = "objects";
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct ImportReport {
+  pub new_objects: usize,
+  pub shared_objects: usize,
+  pub refs: usize,
+}
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct GcReport {
+  pub reachable_objects: usize,
+  // Unreachable objects that were removed, or would be on a dry run.
+  pub removed: Vec<ObjectId>,
+  pub removed_bytes: u64,
+  // Unreachable objects spared because they are younger than the grace period.
+  pub retained: Vec<ObjectId>,
+}
+
+// A line of the refs file whose fragment does not parse. It is written back
+// unchanged and its target is kept from GC until the line is fixed by hand.
+#[derive(Debug, PartialEq, Clone)]
+pub struct InvalidRef {
+  pub fragment: String,
+  pub name: String,
+  pub oid: ObjectId,
+  pub reason: String,
 }
 
-impl<'a> BundledRepository<'a> {
-  pub fn new() -> Result<BundledRepository<'a>, Error> {
-    let odb: Odb<'a> = match Odb::new() {
+pub struct BundledRepository {
+  storage: Box<dyn Storage>,
+  // Refs of every imported repository.
+  refs: NamespaceRefManager,
+  invalid_refs: Vec<InvalidRef>,
+  object_format: ObjectFormat,
+  // Set for bundles living in a directory; refs are written back on change.
+  path: Option<PathBuf>,
+  // Behind a mutex so that lookups through `&self` can update it.
+  cache: Option<Mutex<ObjectCache>>,
+}
+
+impl BundledRepository {
+  pub fn new() -> Result<BundledRepository, Error> {
+    BundledRepository::with_object_format(ObjectFormat::Sha1)
+  }
+
+  pub fn with_object_format(object_format: ObjectFormat) -> Result<BundledRepository, Error> {
+    BundledRepository::with_storage(Box::new(MemoryStorage::new()), object_format)
+  }
+
+  pub fn with_storage(
+    storage: Box<dyn Storage>,
+    object_format: ObjectFormat,
+  ) -> Result<BundledRepository, Error> {
+    let ret = BundledRepository {
+      storage,
+      refs: NamespaceRefManager::new(),
+      invalid_refs: Vec::new(),
+      object_format,
+      path: None,
+      cache: None,
+    };
+
+    Ok(ret)
+  }
+
+  pub fn create(path: &Path) -> Result<BundledRepository, Error> {
+    BundledRepository::create_with_object_format(path, ObjectFormat::Sha1)
+  }
+
+  pub fn create_with_object_format(
+    path: &Path,
+    object_format: ObjectFormat,
+  ) -> Result<BundledRepository, Error> {
+    let metadata_path = path.join(METADATA_FILE);
+    if metadata_path.exists() {
+      return Err(Error::BundleAlreadyExists(
+        path.to_string_lossy().into_owned(),
+      ));
+    }
+
+    let storage = LooseObjectStorage::new(&path.join(OBJECTS_DIR), object_format)?;
+    let mut ret = BundledRepository::with_storage(Box::new(storage), object_format)?;
+    ret.path = Some(path.to_path_buf());
+    RefsLock::acquire(path)?.commit("")?;
+    let metadata = format!(
+      "version {}\nobject-format {}\n",
+      BUNDLE_VERSION, ret.object_format
+    );
+    io_result(fs::write(&metadata_path, metadata))?;
+
+    Ok(ret)
+  }
+
+  pub fn open(path: &Path) -> Result<BundledRepository, Error> {
+    let metadata = match fs::read_to_string(path.join(METADATA_FILE)) {
       Ok(v) => Ok(v),
-      Err(err) => Err(Error::OdbInitializationFailed {
-        detail: err.message().to_owned(),
-      }),
+      Err(_) => Err(malformed_bundle("metadata not found")),
     }?;
+    let (version, object_format) = parse_metadata(&metadata)?;
+    if version > BUNDLE_VERSION {
+      return Err(Error::UnsupportedBundleVersion {
+        version,
+        supported: BUNDLE_VERSION,
+      });
+    }
+
+    let storage = LooseObjectStorage::new(&path.join(OBJECTS_DIR), object_format)?;
+    let mut ret = BundledRepository::with_storage(Box::new(storage), object_format)?;
+    let (refs, invalid_refs) = parse_refs(
+      &io_result(fs::read_to_string(path.join(REFS_FILE)))?,
+      object_format,
+    )?;
+    ret.refs = refs;
+    ret.invalid_refs = invalid_refs;
+    ret.path = Some(path.to_path_buf());
+
+    Ok(ret)
+  }
+
+  pub fn path(&self) -> Option<&Path> {
+    self.path.as_deref()
+  }
+
+  pub fn object_format(&self) -> ObjectFormat {
+    self.object_format
+  }
+
+  pub fn insert(&mut self, object: &GitObject) -> Result<ObjectId, Error> {
+    let oid = object.object_id(self.object_format);
+    // Storing it again refreshes its age, which keeps GC from removing an
+    // object that was just written.
+    self.storage.put(oid, &object.to_u8_vec()?)?;
+
+    Ok(oid)
+  }
+
+  pub fn enable_cache(&mut self, capacity: usize) {
+    self.cache = Some(Mutex::new(ObjectCache::new(capacity)));
+  }
 
-    let ret = BundledRepository { odb };
+  pub fn disable_cache(&mut self) {
+    self.cache = None;
+  }
+
+  pub fn cache_stats(&self) -> Option<CacheStats> {
+    self.cache.as_ref().map(|v| lock_cache(v).stats())
+  }
+
+  pub fn find(&self, oid: &ObjectId) -> Result<Option<Arc<GitObject>>, Error> {
+    if let Some(cache) = &self.cache {
+      if let Some(object) = lock_cache(cache).get(oid) {
+        return Ok(Some(object));
+      }
+    }
+
+    let object = match self.storage.get(oid)? {
+      Some(data) => Arc::new(GitObject::from_u8_vec_with_oid(&data, oid)?),
+      None => return Ok(None),
+    };
+    if let Some(cache) = &self.cache {
+      lock_cache(cache).insert(*oid, Arc::clone(&object));
+    }
+
+    Ok(Some(object))
+  }
+
+  // Returns the stored zlib bytes without decoding or verifying them.
+  pub fn find_raw(&self, oid: &ObjectId) -> Result<Option<Vec<u8>>, Error> {
+    self.storage.get(oid)
+  }
+
+  pub fn stored_size(&self, oid: &ObjectId) -> Result<Option<u64>, Error> {
+    self.storage.size(oid)
+  }
+
+  pub fn exists(&self, oid: &ObjectId) -> Result<bool, Error> {
+    self.storage.contains(oid)
+  }
+
+  pub fn oids(&self) -> Result<Vec<ObjectId>, Error> {
+    self.storage.oids()
+  }
+
+  pub fn len(&self) -> Result<usize, Error> {
+    Ok(self.storage.oids()?.len())
+  }
+
+  pub fn is_empty(&self) -> Result<bool, Error> {
+    Ok(self.len()? == 0)
+  }
+
+  pub fn reachable_oids(&self, roots: &[ObjectId]) -> Result<Vec<ObjectId>, Error> {
+    let mut ret: Vec<ObjectId> = Vec::new();
+    let mut visited: HashSet<ObjectId> = HashSet::new();
+    let mut pending: Vec<ObjectId> = roots.to_vec();
+    while let Some(oid) = pending.pop() {
+      if !visited.insert(oid) {
+        continue;
+      }
+
+      let object = match self.find(&oid)? {
+        Some(v) => Ok(v),
+        None => Err(Error::ObjectNotFound(oid.to_string())),
+      }?;
+      pending.extend(references(&object, self.object_format)?);
+      ret.push(oid);
+    }
 
     Ok(ret)
   }
+
+  pub fn dedup_report(&self) -> Result<DedupReport, Error> {
+    DedupReport::build(self)
+  }
+
+  pub fn fsck(&self) -> Result<FsckReport, Error> {
+    FsckReport::build(self)
+  }
+
+  pub fn refs(&self, fragment: &PathFragment) -> Option<BTreeMap<String, ObjectId>> {
+    self.refs.refs(fragment)
+  }
+
+  pub fn fragments(&self) -> impl Iterator<Item = &PathFragment> {
+    self.refs.fragments()
+  }
+
+  pub fn invalid_refs(&self) -> &[InvalidRef] {
+    &self.invalid_refs
+  }
+
+  pub fn set_refs(
+    &mut self,
+    fragment: &PathFragment,
+    refs: BTreeMap<String, ObjectId>,
+  ) -> Result<(), Error> {
+    for name in refs.keys() {
+      check_ref_name(name)?;
+    }
+
+    self.update_refs(|_, v| {
+      v.set_refs(fragment, refs);
+      Ok(())
+    })
+  }
+
+  // Sets the given refs of `fragment` and keeps the ones not mentioned.
+  pub fn merge_refs(
+    &mut self,
+    fragment: &PathFragment,
+    refs: BTreeMap<String, ObjectId>,
+  ) -> Result<(), Error> {
+    for name in refs.keys() {
+      check_ref_name(name)?;
+    }
+
+    self.update_refs(|_, v| {
+      for (name, oid) in refs.iter() {
+        v.insert(fragment, name, *oid);
+      }
+      Ok(())
+    })
+  }
+
+  pub fn commit_transaction(&mut self, transaction: &RefTransaction) -> Result<(), Error> {
+    self.update_refs(|repository, refs| repository.apply_transaction(transaction, refs))
+  }
+
+  // Check every update before touching anything so a rejected one leaves all
+  // refs as they were.
+  fn apply_transaction(
+    &self,
+    transaction: &RefTransaction,
+    refs: &mut NamespaceRefManager,
+  ) -> Result<(), Error> {
+    let mut seen: HashSet<String> = HashSet::new();
+    for update in transaction.updates() {
+      let reference = update.reference();
+      if !seen.insert(reference.clone()) {
+        return Err(Error::DuplicateRefUpdate(reference));
+      }
+
+      let actual = refs.get(&update.fragment, &update.name);
+      if actual != update.expected {
+        return Err(Error::RefConflict {
+          reference,
+          expected: describe_oid(update.expected),
+          actual: describe_oid(actual),
+        });
+      }
+
+      match update.new {
+        Some(oid) => {
+          if !self.exists(&oid)? {
+            return Err(Error::RefTargetNotFound {
+              reference,
+              oid: oid.to_string(),
+            });
+          }
+          refs.insert(&update.fragment, &update.name, oid);
+        }
+        None => {
+          refs.remove(&update.fragment, &update.name);
+        }
+      }
+    }
+
+    Ok(())
+  }
+
+  pub fn remove_refs(&mut self, fragment: &PathFragment) -> Result<bool, Error> {
+    self.update_refs(|_, v| Ok(v.remove_fragment(fragment)))
+  }
+
+  // Every ref write goes through here. `update` runs against the refs on disk,
+  // read while holding the lock, because another handle on the same bundle may
+  // have changed them; the handle's own refs change only once the new ones are
+  // written.
+  fn update_refs<T, F>(&mut self, update: F) -> Result<T, Error>
+  where
+    F: FnOnce(&BundledRepository, &mut NamespaceRefManager) -> Result<T, Error>,
+  {
+    let path = match &self.path {
+      Some(v) => v.clone(),
+      None => {
+        let mut refs = self.refs.clone();
+        let ret = update(self, &mut refs)?;
+        self.refs = refs;
+        return Ok(ret);
+      }
+    };
+
+    let lock = RefsLock::acquire(&path)?;
+    let (mut refs, invalid_refs) = parse_refs(
+      &io_result(fs::read_to_string(path.join(REFS_FILE)))?,
+      self.object_format,
+    )?;
+    let ret = update(self, &mut refs)?;
+    lock.commit(&format_refs(&refs, &invalid_refs)?)?;
+    self.refs = refs;
+    self.invalid_refs = invalid_refs;
+
+    Ok(ret)
+  }
+
+  pub fn gc(&mut self, grace_period: Duration, dry_run: bool) -> Result<GcReport, Error> {
+    let roots: Vec<ObjectId> = self
+      .refs
+      .iter()
+      .map(|(_, _, oid)| oid)
+      .chain(self.invalid_refs.iter().map(|v| v.oid))
+      .collect();
+    let reachable: HashSet<ObjectId> = self.reachable_oids(&roots)?.into_iter().collect();
+
+    let now = SystemTime::now();
+    let mut report = GcReport {
+      reachable_objects: reachable.len(),
+      ..GcReport::default()
+    };
+    for oid in self.storage.oids()? {
+      if reachable.contains(&oid) {
+        continue;
+      }
+
+      let expired = match self.storage.stored_at(&oid)? {
+        Some(time) => match now.duration_since(time) {
+          Ok(age) => age >= grace_period,
+          // Written "in the future" by a skewed clock; treat it as fresh.
+          Err(_) => grace_period == Duration::from_secs(0),
+        },
+        None => true,
+      };
+      if !expired {
+        report.retained.push(oid);
+        continue;
+      }
+
+      report.removed_bytes += self.storage.size(&oid)?.unwrap_or(0);
+      if !dry_run {
+        self.storage.remove(&oid)?;
+        if let Some(cache) = &self.cache {
+          lock_cache(cache).remove(&oid);
+        }
+      }
+      report.removed.push(oid);
+    }
+    if !dry_run && !report.removed.is_empty() {
+      self.storage.compact()?;
+    }
+
+    Ok(report)
+  }
+
+  pub fn import(&mut self, path: &Path, fragment: &PathFragment) -> Result<ImportReport, Error> {
+    let repository = GitDirectory::open(path)?;
+    if repository.object_format() != self.object_format {
+      return Err(Error::ObjectFormatMismatch {
+        expected: self.object_format.to_string(),
+        actual: repository.object_format().to_string(),
+      });
+    }
+    let refs = repository.refs()?;
+
+    let mut report = ImportReport::default();
+    let mut visited: HashSet<ObjectId> = HashSet::new();
+    let mut pending: Vec<ObjectId> = refs.values().cloned().collect();
+    while let Some(oid) = pending.pop() {
+      if !visited.insert(oid) {
+        continue;
+      }
+
+      let object = match repository.read(&oid)? {
+        Some(v) => Ok(v),
+        None => Err(Error::ObjectNotFound(oid.to_string())),
+      }?;
+      let links = references(&object, self.object_format)?;
+      if self.exists(&oid)? {
+        report.shared_objects += 1;
+      } else {
+        self.insert(&object)?;
+        report.new_objects += 1;
+      }
+      pending.extend(links);
+    }
+
+    report.refs = refs.len();
+    self.set_refs(fragment, refs)?;
+
+    Ok(report)
+  }
 }
 
-impl<'a> fmt::Display for BundledRepository<'a> {
+// Cache operations do not call out to user code, so a poisoned lock only
+// means another thread panicked elsewhere; keep using the cache.
+fn lock_cache(cache: &Mutex<ObjectCache>) -> MutexGuard<'_, ObjectCache> {
+  match cache.lock() {
+    Ok(v) => v,
+    Err(err) => err.into_inner(),
+  }
+}
+
+// `refs.lock`, created exclusively the way git locks a ref. The new refs are
+// written into it and renamed over the refs file, so a crash never leaves half
+// of them; dropping the lock without committing removes it.
+struct RefsLock {
+  path: PathBuf,
+  file: File,
+  committed: bool,
+}
+
+impl RefsLock {
+  fn acquire(dir: &Path) -> Result<RefsLock, Error> {
+    let path = dir.join(format!("{}.lock", REFS_FILE));
+    match OpenOptions::new().write(true).create_new(true).open(&path) {
+      Ok(file) => Ok(RefsLock {
+        path,
+        file,
+        committed: false,
+      }),
+      Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => {
+        Err(Error::RefsLocked(path.to_string_lossy().into_owned()))
+      }
+      Err(err) => io_result(Err(err)),
+    }
+  }
+
+  fn commit(mut self, content: &str) -> Result<(), Error> {
+    io_result(
+      self
+        .file
+        .write_all(content.as_bytes())
+        .and_then(|_| self.file.sync_all()),
+    )?;
+    let target = self.path.with_file_name(REFS_FILE);
+    io_result(fs::rename(&self.path, target))?;
+    self.committed = true;
+
+    Ok(())
+  }
+}
+
+impl Drop for RefsLock {
+  fn drop(&mut self) {
+    if !self.committed {
+      let _ = fs::remove_file(&self.path);
+    }
+  }
+}
+
+fn format_refs(refs: &NamespaceRefManager, invalid_refs: &[InvalidRef]) -> Result<String, Error> {
+  let mut ret = String::new();
+  for (fragment, name, oid) in refs.iter() {
+    check_ref_name(name)?;
+    ret.push_str(&format!("{} {} {}\n", oid, fragment.to_string(), name));
+  }
+  for v in invalid_refs.iter() {
+    ret.push_str(&format!("{} {} {}\n", v.oid, v.fragment, v.name));
+  }
+
+  Ok(ret)
+}
+
+fn describe_oid(oid: Option<ObjectId>) -> String {
+  match oid {
+    Some(v) => v.to_string(),
+    None => "none".to_owned(),
+  }
+}
+
+fn parse_metadata(metadata: &str) -> Result<(u32, ObjectFormat), Error> {
+  let mut version: Option<u32> = None;
+  let mut object_format: Option<ObjectFormat> = None;
+  for line in metadata.lines() {
+    match line.split_once(' ') {
+      Some(("version", v)) => match v.parse() {
+        Ok(v) => version = Some(v),
+        Err(_) => return Err(malformed_bundle("invalid version")),
+      },
+      Some(("object-format", v)) => object_format = Some(ObjectFormat::from_name(v)?),
+      // Unknown keys are left for newer versions of the layout.
+      _ => continue,
+    }
+  }
+
+  match (version, object_format) {
+    (Some(version), Some(object_format)) => Ok((version, object_format)),
+    (None, _) => Err(malformed_bundle("version not found")),
+    (_, None) => Err(malformed_bundle("object format not found")),
+  }
+}
+
+fn parse_refs(
+  content: &str,
+  object_format: ObjectFormat,
+) -> Result<(NamespaceRefManager, Vec<InvalidRef>), Error> {
+  let mut ret = NamespaceRefManager::new();
+  let mut invalid_refs: Vec<InvalidRef> = Vec::new();
+  for line in content.lines() {
+    let columns: Vec<&str> = line.splitn(3, ' ').collect();
+    let (oid, fragment, name) = match columns.as_slice() {
+      [oid, fragment, name] => (*oid, *fragment, *name),
+      _ => return Err(malformed_bundle(&format!("invalid ref line: {}", line))),
+    };
+    let oid = match parse_hex_oid(oid.as_bytes(), object_format) {
+      Some(v) => Ok(v),
+      None => Err(Error::InvalidObjectId(oid.to_owned())),
+    }?;
+
+    match PathFragment::parse_full_qualified_fragment(fragment) {
+      Ok(v) => ret.insert(&v, name, oid),
+      Err(err) => invalid_refs.push(InvalidRef {
+        fragment: fragment.to_owned(),
+        name: name.to_owned(),
+        oid,
+        reason: err.to_string(),
+      }),
+    }
+  }
+
+  Ok((ret, invalid_refs))
+}
+
+fn malformed_bundle(reason: &str) -> Error {
+  Error::MalformedBundle(reason.to_owned())
+}
+
+pub(crate) fn references(object: &GitObject, format: ObjectFormat) -> Result<Vec<ObjectId>, Error> {
+  match object.object_type() {
+    ObjectType::Commit => {
+      let (tree, parents) = Commit::parse_links_with_format(object.content(), format)?;
+      let mut ret = vec![tree];
+      ret.extend(parents);
+
+      Ok(ret)
+    }
+    ObjectType::Tree => {
+      let tree = TreeObject::from_git_object_with_format(object, format)?;
+      // Submodule commits live in another repository.
+      Ok(
+        tree
+          .entries()
+          .iter()
+          .filter(|v| v.mode != FileMode::Commit)
+          .map(|v| v.oid)
+          .collect(),
+      )
+    }
+    ObjectType::Tag => Ok(vec![
+      Tag::from_git_object_with_format(object, ParseMode::Lenient, format)?.object,
+    ]),
+    _ => Ok(Vec::new()),
+  }
+}
+
+impl fmt::Display for BundledRepository {
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
     write!(f, "BundledRepository")
   }
 }
 
-impl<'a> fmt::Debug for BundledRepository<'a> {
+impl fmt::Debug for BundledRepository {
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
     write!(f, "BundledRepository")
   }
@@ -35,12 +636,677 @@ impl<'a> fmt::Debug for BundledRepository<'a> {
 
 #[cfg(test)]
 mod tests {
+  use super::super::loose_object_storage::LooseObjectStorage;
+  use super::super::single_file_storage::SingleFileStorage;
   use super::*;
+  use crate::test_helper::{blob, commit_file, git, temp_dir};
+  use std::fs;
+
+  fn fragment(fragment: &str) -> PathFragment {
+    PathFragment::parse_full_qualified_fragment(fragment).unwrap()
+  }
+
+  fn reachable_count(dir: &Path) -> usize {
+    git(dir, &["rev-list", "--objects", "--all"])
+      .lines()
+      .count()
+  }
 
   #[test]
   fn test_bundled_repository_new() {
     let actual = BundledRepository::new();
 
     assert!(actual.is_ok());
+    assert_eq!(actual.unwrap().object_format(), ObjectFormat::Sha1);
+  }
+
+  #[test]
+  fn test_bundled_repository_with_object_format() {
+    let mut repository = BundledRepository::with_object_format(ObjectFormat::Sha256).unwrap();
+    let object = blob(b"hello");
+    let oid = repository.insert(&object).unwrap();
+
+    assert_eq!(repository.object_format(), ObjectFormat::Sha256);
+    assert_eq!(oid, ObjectFormat::Sha256.hash(b"blob 5\0hello"));
+    assert_eq!(repository.find(&oid), Ok(Some(Arc::new(object))));
+  }
+
+  #[test]
+  fn test_bundled_repository_insert_and_find() {
+    let mut repository = BundledRepository::new().unwrap();
+    let object = blob(b"hello");
+    let oid = repository.insert(&object).unwrap();
+
+    assert_eq!(oid, ObjectFormat::Sha1.hash(b"blob 5\0hello"));
+    assert_eq!(repository.exists(&oid), Ok(true));
+    assert_eq!(repository.find(&oid), Ok(Some(Arc::new(object))));
+  }
+
+  #[test]
+  fn test_bundled_repository_find_when_object_does_not_exist() {
+    let repository = BundledRepository::new().unwrap();
+
+    assert_eq!(
+      repository.exists(&ObjectId::zero(ObjectFormat::Sha1)),
+      Ok(false)
+    );
+    assert_eq!(
+      repository.find(&ObjectId::zero(ObjectFormat::Sha1)),
+      Ok(None)
+    );
+  }
+
+  #[test]
+  fn test_bundled_repository_oids() {
+    let mut repository = BundledRepository::new().unwrap();
+    let a = repository.insert(&blob(b"a")).unwrap();
+    let b = repository.insert(&blob(b"b")).unwrap();
+    repository.insert(&blob(b"a")).unwrap();
+    let mut expected = vec![a, b];
+    expected.sort();
+
+    assert_eq!(repository.len(), Ok(2));
+    assert_eq!(repository.oids(), Ok(expected));
+  }
+
+  #[test]
+  fn test_bundled_repository_with_storage() {
+    let dir = temp_dir("with-storage");
+    let storages: Vec<Box<dyn Storage>> = vec![
+      Box::new(LooseObjectStorage::new(&dir.join("objects"), ObjectFormat::Sha1).unwrap()),
+      Box::new(SingleFileStorage::new(&dir.join("objects.bin"), ObjectFormat::Sha1).unwrap()),
+    ];
+
+    for storage in storages {
+      let mut repository = BundledRepository::with_storage(storage, ObjectFormat::Sha1).unwrap();
+      let object = blob(b"hello");
+      let oid = repository.insert(&object).unwrap();
+
+      assert_eq!(repository.find(&oid), Ok(Some(Arc::new(object))));
+      assert_eq!(repository.oids(), Ok(vec![oid]));
+    }
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn test_bundled_repository_import() {
+    let dir = temp_dir("import");
+    let origin = dir.join("origin");
+    fs::create_dir_all(&origin).unwrap();
+    git(&origin, &["init", "-q", "."]);
+    commit_file(&origin, "a.txt", "a");
+    fs::create_dir_all(origin.join("src")).unwrap();
+    commit_file(&origin, "src/b.txt", "b");
+    git(&origin, &["tag", "-a", "v1", "-m", "v1"]);
+
+    let mut repository = BundledRepository::new().unwrap();
+    let origin_fragment = fragment("github.com@tett23:ckusro-core");
+    let actual = repository.import(&origin, &origin_fragment).unwrap();
+    let expected_count = reachable_count(&origin);
+
+    assert_eq!(actual.new_objects, expected_count);
+    assert_eq!(actual.shared_objects, 0);
+    assert_eq!(repository.len(), Ok(expected_count));
+
+    let refs = repository.refs(&origin_fragment).unwrap();
+    let head = git(&origin, &["rev-parse", "HEAD"]);
+    let tag = git(&origin, &["rev-parse", "refs/tags/v1"]);
+    assert_eq!(
+      refs.get("HEAD"),
+      Some(&ObjectId::from_hex(ObjectFormat::Sha1, head.trim()).unwrap())
+    );
+    assert_eq!(
+      refs.get("refs/tags/v1"),
+      Some(&ObjectId::from_hex(ObjectFormat::Sha1, tag.trim()).unwrap())
+    );
+    assert_eq!(actual.refs, refs.len());
+
+    let fork = dir.join("fork");
+    git(
+      &dir,
+      &[
+        "clone",
+        "-q",
+        origin.to_str().unwrap(),
+        fork.to_str().unwrap(),
+      ],
+    );
+    commit_file(&fork, "c.txt", "c");
+    let fork_fragment = fragment("github.com@someone:ckusro-core");
+    let actual = repository.import(&fork, &fork_fragment).unwrap();
+
+    // The new commit adds a commit, a root tree and a blob.
+    assert_eq!(actual.new_objects, 3);
+    assert_eq!(actual.shared_objects, reachable_count(&fork) - 3);
+    assert_eq!(repository.len(), Ok(expected_count + 3));
+    assert_eq!(repository.fragments().count(), 2);
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn test_bundled_repository_import_in_sha256_format() {
+    let dir = temp_dir("import-sha256");
+    let origin = dir.join("origin");
+    fs::create_dir_all(&origin).unwrap();
+    git(&origin, &["init", "-q", "--object-format=sha256", "."]);
+    commit_file(&origin, "a.txt", "a");
+    git(&origin, &["gc", "-q"]);
+    commit_file(&origin, "b.txt", "b");
+    let fragment = fragment("github.com@tett23:ckusro-core");
+
+    let mut repository = BundledRepository::new().unwrap();
+    let actual = repository.import(&origin, &fragment);
+    assert_eq!(
+      actual,
+      Err(Error::ObjectFormatMismatch {
+        expected: "sha1".to_owned(),
+        actual: "sha256".to_owned(),
+      })
+    );
+
+    let path = dir.join("bundle");
+    let mut repository =
+      BundledRepository::create_with_object_format(&path, ObjectFormat::Sha256).unwrap();
+    let actual = repository.import(&origin, &fragment).unwrap();
+    let head = git(&origin, &["rev-parse", "HEAD"]);
+    let head = ObjectId::from_hex(ObjectFormat::Sha256, head.trim()).unwrap();
+
+    assert_eq!(actual.new_objects, reachable_count(&origin));
+    assert_eq!(repository.fsck().map(|v| v.is_ok()), Ok(true));
+    drop(repository);
+
+    let actual = BundledRepository::open(&path).unwrap();
+    assert_eq!(actual.object_format(), ObjectFormat::Sha256);
+    assert_eq!(actual.refs(&fragment).unwrap().get("HEAD"), Some(&head));
+    assert_eq!(
+      actual.reachable_oids(&[head]).unwrap().len(),
+      reachable_count(&origin)
+    );
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn test_bundled_repository_import_when_author_is_malformed() {
+    let dir = temp_dir("import-malformed-author");
+    let origin = dir.join("origin");
+    fs::create_dir_all(&origin).unwrap();
+    git(&origin, &["init", "-q", "."]);
+    commit_file(&origin, "a.txt", "a");
+    let tree = git(&origin, &["rev-parse", "HEAD^{tree}"]);
+    let parent = git(&origin, &["rev-parse", "HEAD"]);
+    let content = format!(
+      "tree {}\nparent {}\nauthor broken\ncommitter broken\n\nmessage\n",
+      tree.trim(),
+      parent.trim()
+    );
+    let commit_path = dir.join("commit");
+    fs::write(&commit_path, content).unwrap();
+    let head = git(
+      &origin,
+      &[
+        "hash-object",
+        "-t",
+        "commit",
+        "--literally",
+        "-w",
+        commit_path.to_str().unwrap(),
+      ],
+    );
+    git(&origin, &["update-ref", "HEAD", head.trim()]);
+    let fragment = fragment("github.com@tett23:ckusro-core");
+
+    let mut repository = BundledRepository::new().unwrap();
+    let actual = repository.import(&origin, &fragment).unwrap();
+
+    assert_eq!(actual.new_objects, reachable_count(&origin));
+    assert!(repository
+      .exists(&ObjectId::from_hex(ObjectFormat::Sha1, parent.trim()).unwrap())
+      .unwrap());
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn test_bundled_repository_import_when_path_is_not_repository() {
+    let dir = temp_dir("import-not-repository");
+    let mut repository = BundledRepository::new().unwrap();
+    let actual = repository.import(&dir.join("missing"), &fragment("github.com@a:b"));
+
+    assert!(matches!(actual, Err(Error::RepositoryAccessFailed { .. })));
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn test_bundled_repository_create_and_open() {
+    let dir = temp_dir("create-and-open");
+    let path = dir.join("bundle");
+    let origin = dir.join("origin");
+    fs::create_dir_all(&origin).unwrap();
+    git(&origin, &["init", "-q", "."]);
+    commit_file(&origin, "a.txt", "a");
+    let fragment = fragment("github.com@tett23:ckusro-core");
+
+    let mut repository = BundledRepository::create(&path).unwrap();
+    repository.import(&origin, &fragment).unwrap();
+    let expected_oids = repository.oids().unwrap();
+    let expected_refs = repository.refs(&fragment).unwrap();
+    drop(repository);
+
+    let actual = BundledRepository::open(&path).unwrap();
+    assert_eq!(actual.path(), Some(path.as_path()));
+    assert_eq!(actual.object_format(), ObjectFormat::Sha1);
+    assert_eq!(actual.oids(), Ok(expected_oids));
+    assert_eq!(actual.refs(&fragment), Some(expected_refs));
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn test_bundled_repository_create_when_bundle_exists() {
+    let dir = temp_dir("create-when-exists");
+    BundledRepository::create(&dir).unwrap();
+    let actual = BundledRepository::create(&dir);
+
+    assert_eq!(
+      actual.err(),
+      Some(Error::BundleAlreadyExists(
+        dir.to_string_lossy().into_owned()
+      ))
+    );
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn test_bundled_repository_open_when_version_is_newer() {
+    let dir = temp_dir("open-newer-version");
+    BundledRepository::create(&dir).unwrap();
+    fs::write(dir.join("metadata"), "version 2\nobject-format sha1\n").unwrap();
+    let actual = BundledRepository::open(&dir);
+
+    assert_eq!(
+      actual.err(),
+      Some(Error::UnsupportedBundleVersion {
+        version: 2,
+        supported: 1,
+      })
+    );
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn test_bundled_repository_open_when_metadata_is_missing() {
+    let dir = temp_dir("open-without-metadata");
+    let actual = BundledRepository::open(&dir);
+
+    assert_eq!(
+      actual.err(),
+      Some(Error::MalformedBundle("metadata not found".to_owned()))
+    );
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn test_bundled_repository_gc() {
+    let mut repository = BundledRepository::new().unwrap();
+    let kept = repository.insert(&blob(b"kept")).unwrap();
+    let dropped = repository.insert(&blob(b"dropped")).unwrap();
+    let fragment_a = fragment("github.com@a:repo");
+    let fragment_b = fragment("github.com@b:repo");
+    let mut refs = BTreeMap::new();
+    refs.insert("refs/heads/master".to_owned(), kept);
+    repository.set_refs(&fragment_a, refs).unwrap();
+    let mut refs = BTreeMap::new();
+    refs.insert("refs/heads/master".to_owned(), dropped);
+    repository.set_refs(&fragment_b, refs).unwrap();
+    assert_eq!(repository.remove_refs(&fragment_b), Ok(true));
+
+    let actual = repository.gc(Duration::from_secs(3600), false).unwrap();
+    assert_eq!(actual.reachable_objects, 1);
+    assert_eq!(actual.retained, vec![dropped]);
+    assert!(actual.removed.is_empty());
+
+    let actual = repository.gc(Duration::from_secs(0), true).unwrap();
+    assert_eq!(actual.removed, vec![dropped]);
+    assert_eq!(
+      actual.removed_bytes,
+      repository.stored_size(&dropped).unwrap().unwrap()
+    );
+    assert_eq!(repository.exists(&dropped), Ok(true));
+
+    let actual = repository.gc(Duration::from_secs(0), false).unwrap();
+    assert_eq!(actual.removed, vec![dropped]);
+    assert_eq!(repository.oids(), Ok(vec![kept]));
+  }
+
+  #[test]
+  fn test_bundled_repository_gc_when_ref_target_is_missing() {
+    let mut repository = BundledRepository::new().unwrap();
+    repository.insert(&blob(b"orphan")).unwrap();
+    let mut refs = BTreeMap::new();
+    refs.insert(
+      "refs/heads/master".to_owned(),
+      ObjectId::zero(ObjectFormat::Sha1),
+    );
+    repository
+      .set_refs(&fragment("github.com@a:repo"), refs)
+      .unwrap();
+    let actual = repository.gc(Duration::from_secs(0), false);
+
+    assert_eq!(
+      actual.err(),
+      Some(Error::ObjectNotFound(
+        ObjectId::zero(ObjectFormat::Sha1).to_string()
+      ))
+    );
+    assert_eq!(repository.len(), Ok(1));
+  }
+
+  #[test]
+  fn test_bundled_repository_find_with_cache() {
+    let mut repository = BundledRepository::new().unwrap();
+    let object = blob(b"cached");
+    let oid = repository.insert(&object).unwrap();
+    assert_eq!(repository.cache_stats(), None);
+
+    repository.enable_cache(1024);
+    let first = repository.find(&oid).unwrap().unwrap();
+    let second = repository.find(&oid).unwrap().unwrap();
+    assert_eq!(*first, object);
+    // A hit hands out the cached object itself rather than a copy.
+    assert!(Arc::ptr_eq(&first, &second));
+    assert_eq!(
+      repository.find(&ObjectId::zero(ObjectFormat::Sha1)),
+      Ok(None)
+    );
+
+    let stats = repository.cache_stats().unwrap();
+    assert_eq!(stats.hits, 1);
+    assert_eq!(stats.misses, 2);
+    assert_eq!(stats.objects, 1);
+
+    // Nothing refers to the object, so GC drops it and the cache with it.
+    repository.gc(Duration::from_secs(0), false).unwrap();
+    assert_eq!(repository.find(&oid), Ok(None));
+  }
+
+  #[test]
+  fn test_bundled_repository_commit_transaction() {
+    let dir = temp_dir("commit-transaction");
+    let mut repository = BundledRepository::create(&dir).unwrap();
+    let first = repository.insert(&blob(b"first")).unwrap();
+    let second = repository.insert(&blob(b"second")).unwrap();
+    let fragment_a = fragment("github.com@a:repo");
+    let fragment_b = fragment("github.com@b:repo");
+
+    let mut transaction = RefTransaction::new();
+    transaction
+      .update(&fragment_a, "refs/heads/master", None, first)
+      .unwrap()
+      .update(&fragment_b, "refs/heads/master", None, first)
+      .unwrap();
+    repository.commit_transaction(&transaction).unwrap();
+
+    let mut transaction = RefTransaction::new();
+    transaction
+      .update(&fragment_a, "refs/heads/master", Some(first), second)
+      .unwrap()
+      .delete(&fragment_b, "refs/heads/master", first)
+      .unwrap();
+    repository.commit_transaction(&transaction).unwrap();
+
+    let reopened = BundledRepository::open(&dir).unwrap();
+    assert_eq!(
+      reopened.refs(&fragment_a).unwrap().get("refs/heads/master"),
+      Some(&second)
+    );
+    assert_eq!(reopened.refs(&fragment_b), None);
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn test_bundled_repository_commit_transaction_when_expected_oid_differs() {
+    let mut repository = BundledRepository::new().unwrap();
+    let first = repository.insert(&blob(b"first")).unwrap();
+    let second = repository.insert(&blob(b"second")).unwrap();
+    let fragment_a = fragment("github.com@a:repo");
+    let fragment_b = fragment("github.com@b:repo");
+    let mut transaction = RefTransaction::new();
+    transaction
+      .update(&fragment_a, "refs/heads/master", None, first)
+      .unwrap();
+    repository.commit_transaction(&transaction).unwrap();
+
+    let mut transaction = RefTransaction::new();
+    transaction
+      .update(&fragment_b, "refs/heads/master", None, second)
+      .unwrap()
+      .update(&fragment_a, "refs/heads/master", Some(second), second)
+      .unwrap();
+    let actual = repository.commit_transaction(&transaction);
+
+    assert_eq!(
+      actual,
+      Err(Error::RefConflict {
+        reference: "github.com@a:repo refs/heads/master".to_owned(),
+        expected: second.to_string(),
+        actual: first.to_string(),
+      })
+    );
+    assert_eq!(repository.refs(&fragment_b), None);
+    assert_eq!(
+      repository
+        .refs(&fragment_a)
+        .unwrap()
+        .get("refs/heads/master"),
+      Some(&first)
+    );
+  }
+
+  #[test]
+  fn test_bundled_repository_commit_transaction_when_target_is_missing() {
+    let mut repository = BundledRepository::new().unwrap();
+    let mut transaction = RefTransaction::new();
+    transaction
+      .update(
+        &fragment("github.com@a:repo"),
+        "HEAD",
+        None,
+        ObjectId::zero(ObjectFormat::Sha1),
+      )
+      .unwrap();
+    let actual = repository.commit_transaction(&transaction);
+
+    assert_eq!(
+      actual,
+      Err(Error::RefTargetNotFound {
+        reference: "github.com@a:repo HEAD".to_owned(),
+        oid: ObjectId::zero(ObjectFormat::Sha1).to_string(),
+      })
+    );
+    assert_eq!(repository.fragments().count(), 0);
+  }
+
+  #[test]
+  fn test_bundled_repository_commit_transaction_when_ref_is_duplicated() {
+    let mut repository = BundledRepository::new().unwrap();
+    let oid = repository.insert(&blob(b"first")).unwrap();
+    let fragment = fragment("github.com@a:repo");
+    let mut transaction = RefTransaction::new();
+    transaction
+      .update(&fragment, "HEAD", None, oid)
+      .unwrap()
+      .update(&fragment, "HEAD", Some(oid), oid)
+      .unwrap();
+    let actual = repository.commit_transaction(&transaction);
+
+    assert_eq!(
+      actual,
+      Err(Error::DuplicateRefUpdate(
+        "github.com@a:repo HEAD".to_owned()
+      ))
+    );
+  }
+
+  #[test]
+  fn test_bundled_repository_commit_transaction_when_refs_are_locked() {
+    let dir = temp_dir("commit-transaction-locked");
+    let mut repository = BundledRepository::create(&dir).unwrap();
+    let oid = repository.insert(&blob(b"first")).unwrap();
+    let fragment = fragment("github.com@a:repo");
+    // Held by another writer.
+    fs::write(dir.join("refs.lock"), "").unwrap();
+    let mut transaction = RefTransaction::new();
+    transaction.update(&fragment, "HEAD", None, oid).unwrap();
+    let actual = repository.commit_transaction(&transaction);
+
+    assert_eq!(
+      actual,
+      Err(Error::RefsLocked(
+        dir.join("refs.lock").to_string_lossy().into_owned()
+      ))
+    );
+    assert_eq!(repository.refs(&fragment), None);
+    assert!(dir.join("refs.lock").exists());
+
+    fs::remove_file(dir.join("refs.lock")).unwrap();
+    repository.commit_transaction(&transaction).unwrap();
+    assert!(!dir.join("refs.lock").exists());
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn test_bundled_repository_commit_transaction_rereads_refs() {
+    let dir = temp_dir("commit-transaction-reread");
+    let mut repository = BundledRepository::create(&dir).unwrap();
+    let first = repository.insert(&blob(b"first")).unwrap();
+    let second = repository.insert(&blob(b"second")).unwrap();
+    let mut other = BundledRepository::open(&dir).unwrap();
+    let fragment = fragment("github.com@a:repo");
+
+    let mut transaction = RefTransaction::new();
+    transaction.update(&fragment, "HEAD", None, first).unwrap();
+    other.commit_transaction(&transaction).unwrap();
+
+    // This handle has not seen the other one's update.
+    let actual = repository.commit_transaction(&transaction);
+    assert_eq!(
+      actual,
+      Err(Error::RefConflict {
+        reference: "github.com@a:repo HEAD".to_owned(),
+        expected: "none".to_owned(),
+        actual: first.to_string(),
+      })
+    );
+    assert!(!dir.join("refs.lock").exists());
+
+    let mut transaction = RefTransaction::new();
+    transaction
+      .update(&fragment, "HEAD", Some(first), second)
+      .unwrap();
+    repository.commit_transaction(&transaction).unwrap();
+    assert_eq!(
+      BundledRepository::open(&dir)
+        .unwrap()
+        .refs(&fragment)
+        .unwrap()
+        .get("HEAD"),
+      Some(&second)
+    );
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn test_bundled_repository_set_refs_keeps_refs_of_other_handles() {
+    let dir = temp_dir("set-refs-other-handles");
+    let mut repository = BundledRepository::create(&dir).unwrap();
+    let oid = repository.insert(&blob(b"first")).unwrap();
+    let mut other = BundledRepository::open(&dir).unwrap();
+    let fragment_a = fragment("github.com@a:repo");
+    let fragment_b = fragment("github.com@b:repo");
+    let mut refs = BTreeMap::new();
+    refs.insert("HEAD".to_owned(), oid);
+
+    other.set_refs(&fragment_a, refs.clone()).unwrap();
+    repository.set_refs(&fragment_b, refs.clone()).unwrap();
+    assert_eq!(repository.refs(&fragment_a), Some(refs.clone()));
+
+    other.remove_refs(&fragment_a).unwrap();
+    assert_eq!(other.refs(&fragment_b), Some(refs.clone()));
+
+    let actual = BundledRepository::open(&dir).unwrap();
+    assert_eq!(actual.refs(&fragment_a), None);
+    assert_eq!(actual.refs(&fragment_b), Some(refs));
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn test_bundled_repository_set_refs_when_refs_are_locked() {
+    let dir = temp_dir("set-refs-locked");
+    let mut repository = BundledRepository::create(&dir).unwrap();
+    let oid = repository.insert(&blob(b"first")).unwrap();
+    let fragment = fragment("github.com@a:repo");
+    let mut refs = BTreeMap::new();
+    refs.insert("HEAD".to_owned(), oid);
+    repository.set_refs(&fragment, refs.clone()).unwrap();
+    fs::write(dir.join("refs.lock"), "").unwrap();
+
+    assert!(matches!(
+      repository.set_refs(&fragment, BTreeMap::new()),
+      Err(Error::RefsLocked(_))
+    ));
+    assert!(matches!(
+      repository.remove_refs(&fragment),
+      Err(Error::RefsLocked(_))
+    ));
+    assert_eq!(repository.refs(&fragment), Some(refs));
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn test_bundled_repository_merge_refs() {
+    let mut repository = BundledRepository::new().unwrap();
+    let first = repository.insert(&blob(b"first")).unwrap();
+    let second = repository.insert(&blob(b"second")).unwrap();
+    let fragment = fragment("github.com@a:repo");
+    let mut refs = BTreeMap::new();
+    refs.insert("HEAD".to_owned(), first);
+    refs.insert("refs/heads/master".to_owned(), first);
+    repository.set_refs(&fragment, refs).unwrap();
+
+    let mut refs = BTreeMap::new();
+    refs.insert("refs/heads/master".to_owned(), second);
+    repository.merge_refs(&fragment, refs).unwrap();
+
+    let mut expected = BTreeMap::new();
+    expected.insert("HEAD".to_owned(), first);
+    expected.insert("refs/heads/master".to_owned(), second);
+    assert_eq!(repository.refs(&fragment), Some(expected));
+  }
+
+  #[test]
+  fn test_bundled_repository_set_refs_when_name_is_invalid() {
+    let mut repository = BundledRepository::new().unwrap();
+    let oid = repository.insert(&blob(b"first")).unwrap();
+    let mut refs = BTreeMap::new();
+    refs.insert("refs/heads/a\nb".to_owned(), oid);
+    let actual = repository.set_refs(&fragment("github.com@a:repo"), refs);
+
+    assert_eq!(
+      actual,
+      Err(Error::InvalidRefName("refs/heads/a\nb".to_owned()))
+    );
+    assert_eq!(repository.fragments().count(), 0);
   }
 }
@@ -0,0 +1,369 @@
+use super::compressed_git_object::CompressedGitObject;
+use super::error::Error;
+use super::git_object::GitObject;
+use super::object_format::{ObjectFormat, ObjectId};
+use super::object_header::{self, Header};
+use super::signature::{signature_bytes, Signature};
+use git2::ObjectType;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Commit {
+  pub tree: ObjectId,
+  pub parents: Vec<ObjectId>,
+  pub author: Signature,
+  pub committer: Signature,
+  pub extra_headers: Vec<Header>,
+  pub message: Vec<u8>,
+  // The signatures as they were parsed. Re-encoding normalizes values such as
+  // `+0960` or `name<email>`, which would change the object id.
+  raw_author: Option<Vec<u8>>,
+  raw_committer: Option<Vec<u8>>,
+}
+
+impl Commit {
+  pub fn new(
+    tree: ObjectId,
+    parents: Vec<ObjectId>,
+    author: Signature,
+    committer: Signature,
+    message: &[u8],
+  ) -> Commit {
+    Commit {
+      tree,
+      parents,
+      author,
+      committer,
+      extra_headers: Vec::new(),
+      message: message.to_vec(),
+      raw_author: None,
+      raw_committer: None,
+    }
+  }
+
+  pub fn encoding(&self) -> Option<&[u8]> {
+    self.extra_header("encoding")
+  }
+
+  pub fn gpgsig(&self) -> Option<&[u8]> {
+    self.extra_header("gpgsig")
+  }
+
+  pub fn extra_header(&self, key: &str) -> Option<&[u8]> {
+    self
+      .extra_headers
+      .iter()
+      .find(|(k, _)| k == key)
+      .map(|(_, v)| v.as_slice())
+  }
+
+  pub fn from_u8_vec(data: &Vec<u8>) -> Result<Commit, Error> {
+    Commit::from_u8_vec_with_format(data, ObjectFormat::Sha1)
+  }
+
+  pub fn from_u8_vec_with_format(data: &Vec<u8>, format: ObjectFormat) -> Result<Commit, Error> {
+    let compressed = CompressedGitObject::new(data);
+    let (object_type, _, content) = compressed.parse()?;
+    expect_commit(object_type)?;
+
+    Commit::parse_with_format(&content, format)
+  }
+
+  pub fn from_git_object(object: &GitObject) -> Result<Commit, Error> {
+    Commit::from_git_object_with_format(object, ObjectFormat::Sha1)
+  }
+
+  pub fn from_git_object_with_format(
+    object: &GitObject,
+    format: ObjectFormat,
+  ) -> Result<Commit, Error> {
+    expect_commit(object.object_type())?;
+
+    Commit::parse_with_format(object.content(), format)
+  }
+
+  pub fn parse(content: &[u8]) -> Result<Commit, Error> {
+    Commit::parse_with_format(content, ObjectFormat::Sha1)
+  }
+
+  pub fn parse_with_format(content: &[u8], format: ObjectFormat) -> Result<Commit, Error> {
+    let (section, message) = match object_header::split_body(content) {
+      Some(v) => Ok(v),
+      None => Err(malformed("message separator not found")),
+    }?;
+    let headers = match object_header::parse_headers(section) {
+      Some(v) => Ok(v),
+      None => Err(malformed("invalid header line")),
+    }?;
+    let mut headers = headers.into_iter().peekable();
+
+    let tree = match headers.next() {
+      Some((ref key, ref value)) if key == "tree" => parse_oid(value, format),
+      _ => Err(malformed("tree not found")),
+    }?;
+
+    let mut parents: Vec<ObjectId> = Vec::new();
+    while let Some((key, _)) = headers.peek() {
+      if key != "parent" {
+        break;
+      }
+      if let Some((_, value)) = headers.next() {
+        parents.push(parse_oid(&value, format)?);
+      }
+    }
+
+    let raw_author = match headers.next() {
+      Some((key, value)) if key == "author" => Ok(value),
+      _ => Err(malformed("author not found")),
+    }?;
+    let raw_committer = match headers.next() {
+      Some((key, value)) if key == "committer" => Ok(value),
+      _ => Err(malformed("committer not found")),
+    }?;
+
+    Ok(Commit {
+      tree,
+      parents,
+      author: Signature::parse(&raw_author)?,
+      committer: Signature::parse(&raw_committer)?,
+      extra_headers: headers.collect(),
+      message: message.to_vec(),
+      raw_author: Some(raw_author),
+      raw_committer: Some(raw_committer),
+    })
+  }
+
+  // Reads only the tree and parents, ignoring the signatures and any other
+  // header, so that history with a malformed author line can still be walked.
+  pub fn parse_links_with_format(
+    content: &[u8],
+    format: ObjectFormat,
+  ) -> Result<(ObjectId, Vec<ObjectId>), Error> {
+    let mut lines = content.split(|&v| v == b'\n');
+    let tree = match lines.next().and_then(|v| v.strip_prefix(b"tree ")) {
+      Some(v) => parse_oid(v, format),
+      None => Err(malformed("tree not found")),
+    }?;
+
+    let mut parents: Vec<ObjectId> = Vec::new();
+    for line in lines {
+      match line.strip_prefix(b"parent ") {
+        Some(v) => parents.push(parse_oid(v, format)?),
+        None => break,
+      }
+    }
+
+    Ok((tree, parents))
+  }
+
+  pub fn to_bytes(&self) -> Vec<u8> {
+    let mut ret: Vec<u8> = Vec::new();
+    object_header::write_header(&mut ret, "tree", self.tree.to_string().as_bytes());
+    for parent in &self.parents {
+      object_header::write_header(&mut ret, "parent", parent.to_string().as_bytes());
+    }
+    object_header::write_header(
+      &mut ret,
+      "author",
+      &signature_bytes(&self.author, &self.raw_author),
+    );
+    object_header::write_header(
+      &mut ret,
+      "committer",
+      &signature_bytes(&self.committer, &self.raw_committer),
+    );
+    for (key, value) in &self.extra_headers {
+      object_header::write_header(&mut ret, key, value);
+    }
+    ret.push(b'\n');
+    ret.extend_from_slice(&self.message);
+
+    ret
+  }
+
+  pub fn to_git_object(&self) -> GitObject {
+    let content = self.to_bytes();
+
+    GitObject::new(ObjectType::Commit, content.len() as u64, &content)
+  }
+}
+
+fn expect_commit(object_type: ObjectType) -> Result<(), Error> {
+  match object_type {
+    ObjectType::Commit => Ok(()),
+    actual => Err(Error::ObjectTypeMismatch {
+      expected: ObjectType::Commit.to_string(),
+      actual: actual.to_string(),
+    }),
+  }
+}
+
+fn parse_oid(value: &[u8], format: ObjectFormat) -> Result<ObjectId, Error> {
+  match object_header::parse_hex_oid(value, format) {
+    Some(v) => Ok(v),
+    None => Err(Error::InvalidObjectId(
+      String::from_utf8_lossy(value).into_owned(),
+    )),
+  }
+}
+
+fn malformed(reason: &str) -> Error {
+  Error::MalformedCommit(reason.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const SIGNED_COMMIT: &[u8] = b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+parent 0123456789abcdef0123456789abcdef01234567\n\
+parent 89abcdef0123456789abcdef0123456789abcdef\n\
+author tett23 <tett23@example.com> 1549870000 +0900\n\
+committer tett23 <tett23@example.com> 1549870001 -0000\n\
+encoding ISO-8859-1\n\
+x-custom-header value\n\
+gpgsig -----BEGIN PGP SIGNATURE-----\n \n iQEzBAABCAAdFiEE\n =abcd\n -----END PGP SIGNATURE-----\n\
+\n\
+Merge branches\n\
+\n\
+Detail.\n";
+
+  mod commit {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+      let actual = Commit::parse(SIGNED_COMMIT).unwrap();
+
+      assert_eq!(
+        actual.tree.to_string(),
+        "4b825dc642cb6eb9a060e54bf8d69288fbee4904"
+      );
+      assert_eq!(actual.tree.format(), ObjectFormat::Sha1);
+      assert_eq!(actual.parents.len(), 2);
+      assert_eq!(actual.author.name, b"tett23".to_vec());
+      assert_eq!(actual.author.offset, 540);
+      assert_eq!(actual.committer.time, 1549870001);
+      assert_eq!(actual.encoding(), Some(&b"ISO-8859-1"[..]));
+      assert_eq!(actual.extra_header("x-custom-header"), Some(&b"value"[..]));
+      assert!(actual
+        .gpgsig()
+        .unwrap()
+        .starts_with(b"-----BEGIN PGP SIGNATURE-----\n\n"));
+      assert_eq!(actual.message, b"Merge branches\n\nDetail.\n".to_vec());
+    }
+
+    #[test]
+    fn test_to_bytes_round_trip() {
+      let actual = Commit::parse(SIGNED_COMMIT).unwrap().to_bytes();
+
+      assert_eq!(actual, SIGNED_COMMIT.to_vec());
+    }
+
+    #[test]
+    fn test_to_bytes_keeps_signatures_as_parsed() {
+      let content = b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904
+author a<a@example.com> 0 +0960
+committer a  <a@example.com> 0 -0000
+
+init
+";
+      let mut actual = Commit::parse(content).unwrap();
+
+      assert_eq!(actual.author.offset, 600);
+      assert_eq!(actual.to_bytes(), content.to_vec());
+
+      actual.author.time = 1;
+      assert!(actual
+        .to_bytes()
+        .windows(25)
+        .any(|v| v == b"a <a@example.com> 1 +1000"));
+    }
+
+    #[test]
+    fn test_parse_root_commit() {
+      let content = b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904
+author a <a@example.com> 0 +0000
+committer a <a@example.com> 0 +0000
+
+init
+";
+      let actual = Commit::parse(content).unwrap();
+
+      assert!(actual.parents.is_empty());
+      assert_eq!(actual.encoding(), None);
+      assert_eq!(actual.to_bytes(), content.to_vec());
+    }
+
+    #[test]
+    fn test_parse_when_commit_is_malformed() {
+      let data: Vec<&[u8]> = vec![
+        b"author a <a@example.com> 0 +0000\n\nmessage",
+        b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\nmessage",
+        b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\nauthor a <a@example.com> 0 +0000\n",
+        b"tree 4b825dc6\nauthor a <a@example.com> 0 +0000\ncommitter a <a@example.com> 0 +0000\n\n",
+      ];
+
+      for datum in data {
+        let actual = Commit::parse(datum);
+
+        assert!(actual.is_err());
+      }
+    }
+
+    #[test]
+    fn test_parse_links_with_format() {
+      let content = b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+parent 0123456789abcdef0123456789abcdef01234567\n\
+author broken\n\
+\n\
+message\n";
+      let actual = Commit::parse_links_with_format(content, ObjectFormat::Sha1);
+      let expected = (
+        ObjectId::from_hex(
+          ObjectFormat::Sha1,
+          "4b825dc642cb6eb9a060e54bf8d69288fbee4904",
+        )
+        .unwrap(),
+        vec![ObjectId::from_hex(
+          ObjectFormat::Sha1,
+          "0123456789abcdef0123456789abcdef01234567",
+        )
+        .unwrap()],
+      );
+
+      assert!(Commit::parse(content).is_err());
+      assert_eq!(actual, Ok(expected));
+      assert_eq!(
+        Commit::parse_links_with_format(b"author broken\n", ObjectFormat::Sha1),
+        Err(malformed("tree not found"))
+      );
+    }
+
+    #[test]
+    fn test_parse_with_sha256_format() {
+      let tree = "6ef19b41225c5369f1c104d45d8d85efa9b057b53b14b4b9b939dd74decc5321";
+      let parent = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+      let content = format!(
+        "tree {}\nparent {}\nauthor a <a@example.com> 0 +0000\ncommitter a <a@example.com> 0 +0000\n\ninit\n",
+        tree, parent
+      );
+      let actual = Commit::parse_with_format(content.as_bytes(), ObjectFormat::Sha256).unwrap();
+
+      assert_eq!(actual.tree.to_string(), tree);
+      assert_eq!(actual.tree.format(), ObjectFormat::Sha256);
+      assert_eq!(actual.parents[0].to_string(), parent);
+      assert_eq!(actual.to_bytes(), content.into_bytes());
+      assert!(Commit::parse(&actual.to_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_from_git_object() {
+      let commit = Commit::parse(SIGNED_COMMIT).unwrap();
+      let object = commit.to_git_object();
+      let actual = Commit::from_git_object(&object);
+
+      assert_eq!(object.length(), SIGNED_COMMIT.len() as u64);
+      assert_eq!(actual, Ok(commit));
+    }
+  }
+}
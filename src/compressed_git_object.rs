@@ -1,6 +1,9 @@
 extern crate flate2;
 use super::error::Error;
+use super::parse_mode::ParseMode;
 use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
 use git2::ObjectType;
 use std::io::prelude::*;
 
@@ -15,25 +18,101 @@ impl<'a> CompressedGitObject<'a> {
   }
 
   pub fn parse(&self) -> Result<(ObjectType, u64, Vec<u8>), Error> {
-    let inflated = inflate(self.content)?;
+    self.parse_with_mode(ParseMode::Strict)
+  }
+
+  pub fn parse_with_mode(&self, mode: ParseMode) -> Result<(ObjectType, u64, Vec<u8>), Error> {
+    let (inflated, consumed) = inflate(self.content)?;
     let (header, content) = split_object(&inflated)?;
-    let (object_type, length) = parse_header(&header)?;
+    let (object_type, length) = parse_header(&header, mode)?;
+
+    if mode == ParseMode::Strict {
+      if consumed != self.content.len() {
+        return Err(Error::TrailingData(self.content.len() - consumed));
+      }
+      if length != content.len() as u64 {
+        return Err(Error::LengthMismatch {
+          expected: length,
+          actual: content.len() as u64,
+        });
+      }
+    }
 
     Ok((object_type, length, content))
   }
+
+  pub fn peek_header(&self) -> Result<(ObjectType, u64), Error> {
+    let value: &[u8] = self.content;
+    let mut d = ZlibDecoder::new(value);
+
+    read_header(&mut d)
+  }
 }
 
-fn inflate(value: &Vec<u8>) -> Result<Vec<u8>, Error> {
+pub fn build(
+  object_type: ObjectType,
+  length: u64,
+  content: &[u8],
+  level: Compression,
+) -> Result<Vec<u8>, Error> {
+  let mut encoder = ZlibEncoder::new(Vec::new(), level);
+  let header = build_header(object_type, length);
+
+  let written = encoder
+    .write_all(header.as_bytes())
+    .and_then(|_| encoder.write_all(&[0]))
+    .and_then(|_| encoder.write_all(content));
+  if written.is_err() {
+    return Err(Error::DeflateFailed);
+  }
+
+  match encoder.finish() {
+    Ok(v) => Ok(v),
+    Err(_) => Err(Error::DeflateFailed),
+  }
+}
+
+fn build_header(object_type: ObjectType, length: u64) -> String {
+  format!("{} {}", object_type.str(), length)
+}
+
+fn inflate(value: &Vec<u8>) -> Result<(Vec<u8>, usize), Error> {
   let value: &[u8] = value;
   let mut d = ZlibDecoder::new(value);
   let mut ret: Vec<u8> = Vec::new();
 
   match d.read_to_end(&mut ret) {
-    Ok(_) => Ok(ret),
+    Ok(_) => Ok((ret, d.total_in() as usize)),
     Err(_) => Err(Error::InvalidZlibData),
   }
 }
 
+pub(crate) fn read_header<R: Read>(reader: &mut R) -> Result<(ObjectType, u64), Error> {
+  const MAX_HEADER_LENGTH: usize = 32;
+  let mut header: Vec<u8> = Vec::new();
+  let mut byte = [0; 1];
+
+  loop {
+    match reader.read(&mut byte) {
+      Ok(0) => return Err(Error::NullCharacterNotFound),
+      Ok(_) if byte[0] == 0 => break,
+      Ok(_) => header.push(byte[0]),
+      Err(_) => return Err(Error::InvalidZlibData),
+    }
+
+    if header.len() > MAX_HEADER_LENGTH {
+      return Err(Error::InvalidHeader);
+    }
+  }
+
+  let header = match String::from_utf8(header) {
+    Ok(v) => Ok(v),
+    Err(_) => Err(Error::EncodingError),
+  }?;
+
+  parse_header(&header, ParseMode::Strict)
+}
+
 fn split_object(value: &Vec<u8>) -> Result<(String, Vec<u8>), Error> {
   let pos = find_null_pos(&value)?;
   let (header, content) = value.split_at(pos);
@@ -46,7 +125,11 @@ fn split_object(value: &Vec<u8>) -> Result<(String, Vec<u8>), Error> {
   Ok((header, content))
 }
 
-fn parse_header(header: &str) -> Result<(ObjectType, u64), Error> {
+fn parse_header(header: &str, mode: ParseMode) -> Result<(ObjectType, u64), Error> {
+  if mode == ParseMode::Strict {
+    return parse_strict_header(header);
+  }
+
   let mut header = header.split_whitespace();
 
   let object_type = match header.next() {
@@ -67,7 +150,27 @@ fn parse_header(header: &str) -> Result<(ObjectType, u64), Error> {
   Ok((object_type, length))
 }
 
-fn to_object_type(name: &str) -> Result<ObjectType, Error> {
+// Exactly `<type> SP <decimal>`, the only form git writes.
+fn parse_strict_header(header: &str) -> Result<(ObjectType, u64), Error> {
+  let (object_type, length) = match header.split_once(' ') {
+    Some(v) => Ok(v),
+    None => Err(Error::InvalidHeader),
+  }?;
+  let object_type = to_object_type(object_type)?;
+
+  let digits = !length.is_empty() && length.bytes().all(|v| v.is_ascii_digit());
+  if !digits || (length.len() > 1 && length.starts_with('0')) {
+    return Err(Error::InvalidHeader);
+  }
+  let length = match length.parse() {
+    Ok(v) => Ok(v),
+    Err(_) => Err(Error::InvalidHeader),
+  }?;
+
+  Ok((object_type, length))
+}
+
+pub(crate) fn to_object_type(name: &str) -> Result<ObjectType, Error> {
   match name {
     "blob" => Ok(ObjectType::Blob),
     "tree" => Ok(ObjectType::Tree),
@@ -90,8 +193,6 @@ fn find_null_pos(content: &Vec<u8>) -> Result<usize, Error> {
 #[cfg(test)]
 mod tests {
   use super::*;
-  use flate2::write::ZlibEncoder;
-  use flate2::Compression;
 
   fn deflate(data: &[u8]) -> Vec<u8> {
     let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
@@ -109,15 +210,104 @@ mod tests {
     assert_eq!(actual.unwrap(), expected)
   }
 
+  #[test]
+  fn test_parse_when_length_does_not_match() {
+    let bytes = deflate(b"blob 2\0a");
+    let actual = CompressedGitObject::new(&bytes).parse();
+
+    assert_eq!(
+      actual,
+      Err(Error::LengthMismatch {
+        expected: 2,
+        actual: 1
+      })
+    );
+  }
+
+  #[test]
+  fn test_parse_when_trailing_data_exists() {
+    let mut bytes = deflate(b"blob 1\0a");
+    bytes.extend_from_slice(b"garbage");
+    let actual = CompressedGitObject::new(&bytes).parse();
+
+    assert_eq!(actual, Err(Error::TrailingData(7)));
+  }
+
+  #[test]
+  fn test_parse_with_lenient_mode() {
+    let mut bytes = deflate(b"blob 2\0a");
+    bytes.extend_from_slice(b"garbage");
+    let actual = CompressedGitObject::new(&bytes).parse_with_mode(ParseMode::Lenient);
+    let expected = (ObjectType::Blob, 2, b"a".to_vec());
+
+    assert_eq!(actual, Ok(expected));
+  }
+
+  #[test]
+  fn test_peek_header() {
+    let mut data = b"blob 65536\0".to_vec();
+    data.resize(data.len() + 65536, b'a');
+    let mut bytes = deflate(&data);
+    bytes.truncate(bytes.len() / 2);
+    let compressed = CompressedGitObject::new(&bytes);
+
+    assert_eq!(compressed.peek_header(), Ok((ObjectType::Blob, 65536)));
+    assert!(compressed.parse().is_err());
+  }
+
+  #[test]
+  fn test_peek_header_when_data_is_not_zlib() {
+    let bytes = b"blob 1\0a".to_vec();
+    let actual = CompressedGitObject::new(&bytes).peek_header();
+
+    assert_eq!(actual, Err(Error::InvalidZlibData));
+  }
+
+  #[test]
+  fn test_build() {
+    let actual = build(ObjectType::Blob, 1, b"a", Compression::best());
+    let expected = {
+      let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+      encoder.write_all(b"blob 1\0a").unwrap();
+      encoder.finish().unwrap()
+    };
+
+    assert_eq!(actual, Ok(expected));
+  }
+
+  #[test]
+  fn test_build_header() {
+    let actual = build_header(ObjectType::Commit, 123);
+
+    assert_eq!(actual, "commit 123".to_owned());
+  }
+
   #[test]
   fn test_inflate() {
     let bytes = deflate(b"foo");
     let actual = inflate(&bytes);
-    let expected = b"foo".to_vec();
+    let expected = (b"foo".to_vec(), bytes.len());
 
     assert_eq!(actual, Ok(expected))
   }
 
+  #[test]
+  fn test_read_header() {
+    let mut reader: &[u8] = b"tree 12\0content";
+    let actual = read_header(&mut reader);
+
+    assert_eq!(actual, Ok((ObjectType::Tree, 12)));
+    assert_eq!(reader, b"content");
+  }
+
+  #[test]
+  fn test_read_header_when_null_character_is_missing() {
+    let mut reader: &[u8] = b"tree 12";
+    let actual = read_header(&mut reader);
+
+    assert_eq!(actual, Err(Error::NullCharacterNotFound));
+  }
+
   #[test]
   fn test_split_object() {
     let actual = split_object(&b"blob 1\0a".to_vec());
@@ -148,7 +338,7 @@ mod tests {
   #[test]
   fn test_parse_header() {
     let header = "blob 1";
-    let actual = parse_header(&header).unwrap();
+    let actual = parse_header(&header, ParseMode::Strict).unwrap();
 
     assert_eq!(actual.0, ObjectType::Blob);
     assert_eq!(actual.1, 1);
@@ -157,7 +347,7 @@ mod tests {
   #[test]
   fn test_parse_header_when_arg_does_not_contain_spaces() {
     let header = "blob1";
-    let actual = parse_header(&header);
+    let actual = parse_header(&header, ParseMode::Strict);
 
     assert!(actual.is_err());
   }
@@ -165,11 +355,35 @@ mod tests {
   #[test]
   fn test_parse_header_when_arg_does_not_contain_2_more_spaces() {
     let header = "blob hoge 1";
-    let actual = parse_header(&header);
+    let actual = parse_header(&header, ParseMode::Strict);
 
     assert!(actual.is_err());
   }
 
+  #[test]
+  fn test_parse_header_when_arg_is_not_canonical() {
+    let data = vec![
+      "blob 01", "blob  1", "blob\t1", "blob 1 ", "blob +1", "blob 1 2", "blob ",
+    ];
+
+    for datum in data {
+      assert_eq!(
+        parse_header(datum, ParseMode::Strict),
+        Err(Error::InvalidHeader),
+        "{:?}",
+        datum
+      );
+    }
+    assert_eq!(
+      parse_header("blob 0", ParseMode::Strict),
+      Ok((ObjectType::Blob, 0))
+    );
+    assert_eq!(
+      parse_header("blob  01 ", ParseMode::Lenient),
+      Ok((ObjectType::Blob, 1))
+    );
+  }
+
   #[test]
   fn test_to_object_type() {
     let actual = to_object_type(&"blob");
@@ -0,0 +1,154 @@
+use super::bundled_repository::BundledRepository;
+use super::error::Error;
+use super::object_format::ObjectId;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct NamespaceStats {
+  pub objects: usize,
+  pub bytes: u64,
+  pub unique_objects: usize,
+  pub unique_bytes: u64,
+  pub shared_objects: usize,
+  pub shared_bytes: u64,
+}
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct DedupReport {
+  // Keyed by fragment string.
+  pub namespaces: BTreeMap<String, NamespaceStats>,
+  // Number of objects reachable from both fragments of the pair.
+  pub overlaps: BTreeMap<(String, String), usize>,
+  pub stored_objects: usize,
+  pub stored_bytes: u64,
+  // Objects no ref of any namespace reaches.
+  pub unreferenced_objects: usize,
+  pub unreferenced_bytes: u64,
+  // What the namespaces would take if every one of them were stored alone.
+  pub logical_bytes: u64,
+}
+
+impl DedupReport {
+  pub fn build(repository: &BundledRepository) -> Result<DedupReport, Error> {
+    let mut owners: HashMap<ObjectId, BTreeSet<String>> = HashMap::new();
+    let fragments: Vec<String> = repository.fragments().map(|v| v.to_string()).collect();
+    for fragment in repository.fragments() {
+      let roots: Vec<ObjectId> = repository
+        .refs(fragment)
+        .map(|v| v.values().cloned().collect())
+        .unwrap_or_default();
+      for oid in repository.reachable_oids(&roots)? {
+        owners.entry(oid).or_default().insert(fragment.to_string());
+      }
+    }
+
+    let mut ret = DedupReport::default();
+    for fragment in fragments.iter() {
+      ret
+        .namespaces
+        .insert(fragment.clone(), NamespaceStats::default());
+    }
+
+    for oid in repository.oids()? {
+      let size = repository.stored_size(&oid)?.unwrap_or(0);
+      ret.stored_objects += 1;
+      ret.stored_bytes += size;
+
+      let owners = match owners.get(&oid) {
+        Some(v) => v,
+        None => {
+          ret.unreferenced_objects += 1;
+          ret.unreferenced_bytes += size;
+          continue;
+        }
+      };
+      for fragment in owners.iter() {
+        let stats = ret.namespaces.entry(fragment.clone()).or_default();
+        stats.objects += 1;
+        stats.bytes += size;
+        if owners.len() == 1 {
+          stats.unique_objects += 1;
+          stats.unique_bytes += size;
+        } else {
+          stats.shared_objects += 1;
+          stats.shared_bytes += size;
+        }
+        ret.logical_bytes += size;
+      }
+
+      let owners: Vec<&String> = owners.iter().collect();
+      for (i, a) in owners.iter().enumerate() {
+        for b in owners[i + 1..].iter() {
+          *ret
+            .overlaps
+            .entry(((*a).clone(), (*b).clone()))
+            .or_default() += 1;
+        }
+      }
+    }
+
+    Ok(ret)
+  }
+
+  pub fn saved_bytes(&self) -> u64 {
+    self
+      .logical_bytes
+      .saturating_sub(self.stored_bytes - self.unreferenced_bytes)
+  }
+
+  pub fn overlap(&self, a: &str, b: &str) -> usize {
+    let key = if a < b {
+      (a.to_owned(), b.to_owned())
+    } else {
+      (b.to_owned(), a.to_owned())
+    };
+
+    self.overlaps.get(&key).cloned().unwrap_or(0)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_helper::{blob, set_root, tree};
+
+  #[test]
+  fn test_build() {
+    let mut repository = BundledRepository::new().unwrap();
+    let shared = repository.insert(&blob(b"shared content")).unwrap();
+    let only_a = repository.insert(&blob(b"only in a")).unwrap();
+    let only_b = repository.insert(&blob(b"only in b")).unwrap();
+    let tree_a = repository
+      .insert(&tree(&[("a", only_a), ("shared", shared)]))
+      .unwrap();
+    let tree_b = repository
+      .insert(&tree(&[("b", only_b), ("shared", shared)]))
+      .unwrap();
+    let unreferenced = repository.insert(&blob(b"unreferenced")).unwrap();
+    set_root(&mut repository, "github.com@a:repo", tree_a);
+    set_root(&mut repository, "github.com@b:repo", tree_b);
+
+    let actual = repository.dedup_report().unwrap();
+    let size = |oid: ObjectId| repository.stored_size(&oid).unwrap().unwrap();
+    let stats = &actual.namespaces["github.com@a:repo"];
+
+    assert_eq!(actual.stored_objects, 6);
+    assert_eq!(stats.objects, 3);
+    assert_eq!(stats.unique_objects, 2);
+    assert_eq!(stats.shared_objects, 1);
+    assert_eq!(stats.shared_bytes, size(shared));
+    assert_eq!(stats.unique_bytes, size(only_a) + size(tree_a));
+    assert_eq!(actual.overlap("github.com@b:repo", "github.com@a:repo"), 1);
+    assert_eq!(actual.unreferenced_objects, 1);
+    assert_eq!(actual.unreferenced_bytes, size(unreferenced));
+    assert_eq!(actual.saved_bytes(), size(shared));
+  }
+
+  #[test]
+  fn test_build_empty_repository() {
+    let repository = BundledRepository::new().unwrap();
+    let actual = repository.dedup_report().unwrap();
+
+    assert_eq!(actual, DedupReport::default());
+  }
+}
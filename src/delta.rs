@@ -0,0 +1,357 @@
+use super::error::Error;
+use std::cmp;
+use std::collections::HashMap;
+
+const BLOCK_LENGTH: usize = 16;
+const MAX_COPY_LENGTH: usize = 0x00ff_ffff;
+const MAX_INSERT_LENGTH: usize = 0x7f;
+
+pub fn apply(base: &[u8], delta: &[u8]) -> Result<Vec<u8>, Error> {
+  let (base_size, mut pos) = decode_size(delta)?;
+  if base_size != base.len() as u64 {
+    return Err(Error::DeltaBaseSizeMismatch {
+      expected: base_size,
+      actual: base.len() as u64,
+    });
+  }
+  let (result_size, read) = decode_size(&delta[pos..])?;
+  pos += read;
+  // Every instruction takes at least one byte and copies at most
+  // MAX_COPY_LENGTH, so larger sizes cannot be honest.
+  let remaining = (delta.len() - pos) as u64;
+  if result_size > remaining.saturating_mul(MAX_COPY_LENGTH as u64) {
+    return Err(malformed("result size out of range"));
+  }
+  // Still only a claim; reserve no more than the inputs take and grow from
+  // there.
+  let capacity = cmp::min(result_size, (base.len() + delta.len()) as u64);
+  let mut ret: Vec<u8> = Vec::with_capacity(capacity as usize);
+
+  while pos < delta.len() {
+    let op = delta[pos];
+    pos += 1;
+
+    if op & 0x80 != 0 {
+      let mut offset: usize = 0;
+      let mut size: usize = 0;
+      for i in 0..4 {
+        if op & (1 << i) != 0 {
+          offset |= (read_byte(delta, &mut pos)? as usize) << (8 * i);
+        }
+      }
+      for i in 0..3 {
+        if op & (0x10 << i) != 0 {
+          size |= (read_byte(delta, &mut pos)? as usize) << (8 * i);
+        }
+      }
+      if size == 0 {
+        size = 0x10000;
+      }
+
+      match offset.checked_add(size) {
+        Some(end) if end <= base.len() => ret.extend_from_slice(&base[offset..end]),
+        _ => {
+          return Err(Error::DeltaCopyOutOfRange {
+            offset: offset as u64,
+            size: size as u64,
+            base_length: base.len() as u64,
+          })
+        }
+      }
+    } else if op != 0 {
+      let end = pos + op as usize;
+      if end > delta.len() {
+        return Err(malformed("insert out of range"));
+      }
+      ret.extend_from_slice(&delta[pos..end]);
+      pos = end;
+    } else {
+      return Err(malformed("reserved opcode"));
+    }
+
+    if ret.len() as u64 > result_size {
+      return Err(malformed("result exceeds declared size"));
+    }
+  }
+
+  if ret.len() as u64 != result_size {
+    return Err(Error::LengthMismatch {
+      expected: result_size,
+      actual: ret.len() as u64,
+    });
+  }
+
+  Ok(ret)
+}
+
+pub fn compute(base: &[u8], target: &[u8]) -> Vec<u8> {
+  let mut ret = encode_size(base.len() as u64);
+  ret.extend(encode_size(target.len() as u64));
+
+  // Index the base by fixed-size blocks; the first occurrence of a block wins.
+  let mut blocks: HashMap<&[u8], usize> = HashMap::new();
+  for (i, block) in base.chunks_exact(BLOCK_LENGTH).enumerate() {
+    blocks.entry(block).or_insert(i * BLOCK_LENGTH);
+  }
+
+  let mut pos = 0;
+  let mut insert_start = 0;
+  while pos + BLOCK_LENGTH <= target.len() {
+    let offset = match blocks.get(&target[pos..pos + BLOCK_LENGTH]) {
+      Some(v) => *v,
+      None => {
+        pos += 1;
+        continue;
+      }
+    };
+
+    let length = base[offset..]
+      .iter()
+      .zip(&target[pos..])
+      .take_while(|(a, b)| a == b)
+      .count();
+    write_insert(&mut ret, &target[insert_start..pos]);
+    write_copy(&mut ret, offset, length);
+    pos += length;
+    insert_start = pos;
+  }
+  write_insert(&mut ret, &target[insert_start..]);
+
+  ret
+}
+
+fn write_copy(out: &mut Vec<u8>, offset: usize, length: usize) {
+  let mut offset = offset;
+  let mut rest = length;
+
+  while rest > 0 {
+    let size = rest.min(MAX_COPY_LENGTH);
+    let mut op = 0x80;
+    let mut args: Vec<u8> = Vec::new();
+    for i in 0..4 {
+      let byte = (offset >> (8 * i)) as u8;
+      if byte != 0 {
+        op |= 1 << i;
+        args.push(byte);
+      }
+    }
+    for i in 0..3 {
+      let byte = (size >> (8 * i)) as u8;
+      if byte != 0 {
+        op |= 0x10 << i;
+        args.push(byte);
+      }
+    }
+
+    out.push(op);
+    out.extend(args);
+    offset += size;
+    rest -= size;
+  }
+}
+
+fn write_insert(out: &mut Vec<u8>, data: &[u8]) {
+  for chunk in data.chunks(MAX_INSERT_LENGTH) {
+    out.push(chunk.len() as u8);
+    out.extend_from_slice(chunk);
+  }
+}
+
+pub fn encode_size(size: u64) -> Vec<u8> {
+  let mut ret: Vec<u8> = Vec::new();
+  let mut size = size;
+  while size >= 0x80 {
+    ret.push((size as u8 & 0x7f) | 0x80);
+    size >>= 7;
+  }
+  ret.push(size as u8);
+
+  ret
+}
+
+// Returns the decoded size and the number of bytes it occupied.
+pub fn decode_size(data: &[u8]) -> Result<(u64, usize), Error> {
+  let mut ret: u64 = 0;
+  let mut shift = 0;
+  let mut pos = 0;
+
+  loop {
+    let byte = read_byte(data, &mut pos)?;
+    if shift > 63 {
+      return Err(malformed("size overflow"));
+    }
+    ret |= u64::from(byte & 0x7f) << shift;
+    shift += 7;
+
+    if byte & 0x80 == 0 {
+      return Ok((ret, pos));
+    }
+  }
+}
+
+fn read_byte(delta: &[u8], pos: &mut usize) -> Result<u8, Error> {
+  match delta.get(*pos) {
+    Some(v) => {
+      *pos += 1;
+      Ok(*v)
+    }
+    None => Err(malformed("unexpected end of delta")),
+  }
+}
+
+fn malformed(reason: &str) -> Error {
+  Error::MalformedDelta(reason.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_apply() {
+    let base = b"hello world";
+    // base size 11, result size 12, copy 6 bytes from 0, insert "there!"
+    let delta = b"\x0b\x0c\x90\x06\x06there!";
+    let actual = apply(base, delta);
+
+    assert_eq!(actual, Ok(b"hello there!".to_vec()));
+  }
+
+  #[test]
+  fn test_apply_when_copy_is_out_of_range() {
+    let base = b"hello";
+    let delta = b"\x05\x0a\x91\x02\x0a";
+    let actual = apply(base, delta);
+
+    assert_eq!(
+      actual,
+      Err(Error::DeltaCopyOutOfRange {
+        offset: 2,
+        size: 10,
+        base_length: 5,
+      })
+    );
+  }
+
+  #[test]
+  fn test_apply_when_base_size_does_not_match() {
+    let actual = apply(b"abc", b"\x04\x01\x01x");
+
+    assert_eq!(
+      actual,
+      Err(Error::DeltaBaseSizeMismatch {
+        expected: 4,
+        actual: 3,
+      })
+    );
+  }
+
+  #[test]
+  fn test_apply_when_result_size_does_not_match() {
+    let actual = apply(b"abc", b"\x03\x05\x01x");
+
+    assert_eq!(
+      actual,
+      Err(Error::LengthMismatch {
+        expected: 5,
+        actual: 1,
+      })
+    );
+  }
+
+  #[test]
+  fn test_apply_when_result_size_is_out_of_range() {
+    let actual = apply(b"abc", b"\x03\xff\xff\xff\xff\xff\xff\xff\x7f\x01x");
+
+    assert_eq!(
+      actual,
+      Err(Error::MalformedDelta("result size out of range".to_owned()))
+    );
+  }
+
+  #[test]
+  fn test_apply_when_result_exceeds_declared_size() {
+    let actual = apply(b"abc", b"\x03\x01\x02xy");
+
+    assert_eq!(
+      actual,
+      Err(Error::MalformedDelta(
+        "result exceeds declared size".to_owned()
+      ))
+    );
+  }
+
+  #[test]
+  fn test_apply_when_insert_is_truncated() {
+    let actual = apply(b"abc", b"\x03\x05\x05xy");
+
+    assert_eq!(
+      actual,
+      Err(Error::MalformedDelta("insert out of range".to_owned()))
+    );
+  }
+
+  #[test]
+  fn test_apply_when_opcode_is_reserved() {
+    let actual = apply(b"abc", b"\x03\x01\x00");
+
+    assert_eq!(
+      actual,
+      Err(Error::MalformedDelta("reserved opcode".to_owned()))
+    );
+  }
+
+  #[test]
+  fn test_compute() {
+    let base: Vec<u8> = (0..4096).map(|v| (v % 251) as u8).collect();
+    let mut target = base[100..3000].to_vec();
+    target.extend_from_slice(b"inserted");
+    target.extend_from_slice(&base[..500]);
+    let actual = compute(&base, &target);
+
+    assert!(actual.len() < 64);
+    assert_eq!(apply(&base, &actual), Ok(target));
+  }
+
+  #[test]
+  fn test_compute_without_common_blocks() {
+    let base = b"abc";
+    let target = vec![b'x'; 300];
+    let actual = compute(base, &target);
+
+    assert_eq!(apply(base, &actual), Ok(target));
+  }
+
+  #[test]
+  fn test_compute_large_copy() {
+    let base: Vec<u8> = (0..0x20000).map(|v| (v % 253) as u8).collect();
+    let actual = compute(&base, &base);
+
+    assert!(actual.len() < 16);
+    assert_eq!(apply(&base, &actual), Ok(base));
+  }
+
+  #[test]
+  fn test_encode_size() {
+    assert_eq!(encode_size(0), vec![0x00]);
+    assert_eq!(encode_size(0x7f), vec![0x7f]);
+    assert_eq!(encode_size(624_485), b"\xe5\x8e\x26".to_vec());
+  }
+
+  #[test]
+  fn test_decode_size() {
+    let actual = decode_size(b"\xe5\x8e\x26rest");
+
+    assert_eq!(actual, Ok((624_485, 3)));
+  }
+
+  #[test]
+  fn test_decode_size_when_data_is_truncated() {
+    let actual = decode_size(b"\xe5\x8e");
+
+    assert_eq!(
+      actual,
+      Err(Error::MalformedDelta("unexpected end of delta".to_owned()))
+    );
+  }
+}
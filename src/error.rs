@@ -6,6 +6,8 @@ use failure::Fail;
 pub enum Error {
   #[fail(display = "Null character not found.")]
   InvalidZlibData,
+  #[fail(display = "Deflate failed.")]
+  DeflateFailed,
   #[fail(display = "Null character not found.")]
   NullCharacterNotFound,
   #[fail(display = "Encoding error.")]
@@ -14,10 +16,130 @@ pub enum Error {
   InvalidHeader,
   #[fail(display = "Invalid type name.")]
   InvalidTypeName,
+  #[fail(display = "Length mismatch. expected={}, actual={}", expected, actual)]
+  LengthMismatch { expected: u64, actual: u64 },
+  #[fail(display = "Object too large. length={}, limit={}", length, limit)]
+  ObjectTooLarge { length: u64, limit: u64 },
+  #[fail(display = "Trailing data after zlib stream. length={}", _0)]
+  TrailingData(usize),
   #[fail(display = "Odb initialization failed. detail: {}", detail)]
   OdbInitializationFailed { detail: String },
+  #[fail(display = "Repository access failed. detail: {}", detail)]
+  RepositoryAccessFailed { detail: String },
+  #[fail(display = "Storage operation failed. detail: {}", detail)]
+  StorageFailed { detail: String },
+  #[fail(display = "Lock poisoned.")]
+  LockPoisoned,
+  #[fail(display = "Background task panicked.")]
+  TaskPanicked,
+  #[fail(display = "Background task could not be started. detail: {}", detail)]
+  TaskSpawnFailed { detail: String },
+  #[fail(
+    display = "Ref update rejected. ref={}, expected={}, actual={}",
+    reference, expected, actual
+  )]
+  RefConflict {
+    reference: String,
+    expected: String,
+    actual: String,
+  },
+  #[fail(display = "Ref updated more than once in a transaction. ref={}", _0)]
+  DuplicateRefUpdate(String),
+  #[fail(display = "Invalid ref name. name={:?}", _0)]
+  InvalidRefName(String),
+  #[fail(display = "Refs are locked by another writer. path={}", _0)]
+  RefsLocked(String),
+  #[fail(display = "Ref target not found. ref={}, oid={}", reference, oid)]
+  RefTargetNotFound { reference: String, oid: String },
+  #[fail(display = "Malformed bundle. reason={}", _0)]
+  MalformedBundle(String),
+  #[fail(
+    display = "Unsupported bundle version. version={}, supported={}",
+    version, supported
+  )]
+  UnsupportedBundleVersion { version: u32, supported: u32 },
+  #[fail(display = "Bundle already exists. path={}", _0)]
+  BundleAlreadyExists(String),
+  #[fail(display = "Fragment not found. fragment={}", _0)]
+  FragmentNotFound(String),
+  #[fail(display = "Object not found. oid={}", _0)]
+  ObjectNotFound(String),
+  #[fail(display = "Malformed git bundle. reason={}", _0)]
+  MalformedGitBundle(String),
+  #[fail(
+    display = "Unsupported git bundle version. version={}, format={}",
+    version, format
+  )]
+  UnsupportedGitBundleVersion { version: u32, format: String },
+  #[fail(display = "Bundle prerequisite not found. oid={}", _0)]
+  MissingPrerequisite(String),
+  #[fail(
+    display = "Object id mismatch. expected={}, actual={}",
+    expected, actual
+  )]
+  ObjectIdMismatch { expected: String, actual: String },
+  #[fail(display = "Malformed pack. reason={}", _0)]
+  MalformedPack(String),
+  #[fail(display = "Unsupported pack version. version={}", _0)]
+  UnsupportedPackVersion(u32),
+  #[fail(display = "Pack checksum mismatch.")]
+  PackChecksumMismatch,
+  #[fail(display = "Malformed pack index. reason={}", _0)]
+  MalformedPackIndex(String),
+  #[fail(display = "Unsupported pack index version. version={}", _0)]
+  UnsupportedPackIndexVersion(u32),
+  #[fail(display = "Pack index checksum mismatch.")]
+  PackIndexChecksumMismatch,
+  #[fail(display = "Pack index does not belong to the pack.")]
+  PackIndexMismatch,
+  #[fail(display = "Ambiguous object id. prefix={}", _0)]
+  AmbiguousObjectId(String),
+  #[fail(display = "Delta base not found. base={}", _0)]
+  MissingDeltaBase(String),
+  #[fail(
+    display = "Delta base size mismatch. expected={}, actual={}",
+    expected, actual
+  )]
+  DeltaBaseSizeMismatch { expected: u64, actual: u64 },
+  #[fail(
+    display = "Delta copy out of range. offset={}, size={}, base_length={}",
+    offset, size, base_length
+  )]
+  DeltaCopyOutOfRange {
+    offset: u64,
+    size: u64,
+    base_length: u64,
+  },
+  #[fail(display = "Malformed delta. reason={}", _0)]
+  MalformedDelta(String),
+  #[fail(display = "Unsupported object format. format={}", _0)]
+  UnsupportedObjectFormat(String),
+  #[fail(
+    display = "Object format mismatch. expected={}, actual={}",
+    expected, actual
+  )]
+  ObjectFormatMismatch { expected: String, actual: String },
   #[fail(display = "{} is not match {}", t1, t2)]
   NamespaceMismatch { t1: String, t2: String },
   #[fail(display = "Invalid path fragment. fragment={}", _0)]
   MalformedFragment(String),
+  #[fail(
+    display = "Object type mismatch. expected={}, actual={}",
+    expected, actual
+  )]
+  ObjectTypeMismatch { expected: String, actual: String },
+  #[fail(display = "Truncated tree entry. offset={}", _0)]
+  TruncatedTreeEntry(usize),
+  #[fail(display = "Invalid file mode. mode={}", _0)]
+  InvalidFileMode(String),
+  #[fail(display = "Tree entries are not sorted. name={}", _0)]
+  UnsortedTreeEntries(String),
+  #[fail(display = "Invalid object id. id={}", _0)]
+  InvalidObjectId(String),
+  #[fail(display = "Malformed signature. signature={}", _0)]
+  MalformedSignature(String),
+  #[fail(display = "Malformed commit. reason={}", _0)]
+  MalformedCommit(String),
+  #[fail(display = "Malformed tag. reason={}", _0)]
+  MalformedTag(String),
 }
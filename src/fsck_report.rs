@@ -0,0 +1,280 @@
+use super::bundled_repository::{references, BundledRepository};
+use super::compressed_git_object::CompressedGitObject;
+use super::error::Error;
+use super::git_object::GitObject;
+use super::object_format::{ObjectFormat, ObjectId};
+use std::collections::{BTreeMap, HashSet};
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum FsckProblem {
+  // `referrer` is None when the object is the target of a ref.
+  Missing {
+    oid: ObjectId,
+    referrer: Option<ObjectId>,
+  },
+  Corrupt {
+    oid: ObjectId,
+    reason: String,
+  },
+  HashMismatch {
+    oid: ObjectId,
+    actual: ObjectId,
+  },
+  // The namespace's fragment in the refs file does not parse.
+  InvalidFragment {
+    reason: String,
+  },
+}
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct NamespaceFsck {
+  pub checked_objects: usize,
+  pub problems: Vec<FsckProblem>,
+}
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct FsckReport {
+  // Keyed by fragment string.
+  pub namespaces: BTreeMap<String, NamespaceFsck>,
+  // Stored objects that no ref of any namespace reaches.
+  pub dangling: Vec<ObjectId>,
+}
+
+impl FsckReport {
+  pub fn build(repository: &BundledRepository) -> Result<FsckReport, Error> {
+    let mut ret = FsckReport::default();
+    let mut reachable: HashSet<ObjectId> = HashSet::new();
+
+    for fragment in repository.fragments() {
+      let refs = match repository.refs(fragment) {
+        Some(v) => v,
+        None => continue,
+      };
+      let pending: Vec<(ObjectId, Option<ObjectId>)> = refs.values().map(|v| (*v, None)).collect();
+      let namespace = check_namespace(repository, pending, &mut reachable)?;
+      ret.namespaces.insert(fragment.to_string(), namespace);
+    }
+
+    let mut invalid: BTreeMap<String, (String, Vec<ObjectId>)> = BTreeMap::new();
+    for v in repository.invalid_refs() {
+      invalid
+        .entry(v.fragment.clone())
+        .or_insert_with(|| (v.reason.clone(), Vec::new()))
+        .1
+        .push(v.oid);
+    }
+    for (fragment, (reason, oids)) in invalid {
+      let pending: Vec<(ObjectId, Option<ObjectId>)> =
+        oids.into_iter().map(|v| (v, None)).collect();
+      let mut namespace = check_namespace(repository, pending, &mut reachable)?;
+      namespace
+        .problems
+        .insert(0, FsckProblem::InvalidFragment { reason });
+      ret.namespaces.insert(fragment, namespace);
+    }
+
+    for oid in repository.oids()? {
+      if !reachable.contains(&oid) {
+        ret.dangling.push(oid);
+      }
+    }
+
+    Ok(ret)
+  }
+
+  pub fn is_ok(&self) -> bool {
+    self.namespaces.values().all(|v| v.problems.is_empty())
+  }
+}
+
+fn check_namespace(
+  repository: &BundledRepository,
+  mut pending: Vec<(ObjectId, Option<ObjectId>)>,
+  reachable: &mut HashSet<ObjectId>,
+) -> Result<NamespaceFsck, Error> {
+  let mut ret = NamespaceFsck::default();
+  let mut visited: HashSet<ObjectId> = HashSet::new();
+
+  while let Some((oid, referrer)) = pending.pop() {
+    if !visited.insert(oid) {
+      continue;
+    }
+
+    let data = match repository.find_raw(&oid)? {
+      Some(v) => v,
+      None => {
+        ret.problems.push(FsckProblem::Missing { oid, referrer });
+        continue;
+      }
+    };
+    reachable.insert(oid);
+    ret.checked_objects += 1;
+
+    let object = match check_object(&oid, &data, repository.object_format()) {
+      Ok(v) => v,
+      Err(problem) => {
+        ret.problems.push(problem);
+        continue;
+      }
+    };
+    match references(&object, repository.object_format()) {
+      Ok(v) => pending.extend(v.into_iter().map(|v| (v, Some(oid)))),
+      Err(err) => ret.problems.push(corrupt(&oid, &err)),
+    }
+  }
+
+  Ok(ret)
+}
+
+fn check_object(
+  oid: &ObjectId,
+  data: &Vec<u8>,
+  format: ObjectFormat,
+) -> Result<GitObject, FsckProblem> {
+  let (object_type, length, content) = match CompressedGitObject::new(data).parse() {
+    Ok(v) => Ok(v),
+    Err(err) => Err(corrupt(oid, &err)),
+  }?;
+  let object = GitObject::new(object_type, length, &content);
+  let actual = object.object_id(format);
+  if actual != *oid {
+    return Err(FsckProblem::HashMismatch { oid: *oid, actual });
+  }
+
+  Ok(object)
+}
+
+fn corrupt(oid: &ObjectId, err: &Error) -> FsckProblem {
+  FsckProblem::Corrupt {
+    oid: *oid,
+    reason: err.to_string(),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::storage::{MemoryStorage, Storage};
+  use crate::test_helper::{blob, loose, set_root, temp_dir, tree};
+  use std::fs;
+
+  #[test]
+  fn test_build() {
+    let mut storage = MemoryStorage::new();
+    let (good, data) = loose(&blob(b"good"));
+    storage.put(good, &data).unwrap();
+    let (swapped, _) = loose(&blob(b"expected"));
+    let (actual_oid, data) = loose(&blob(b"actual"));
+    storage.put(swapped, &data).unwrap();
+    let (corrupt, _) = loose(&blob(b"corrupt"));
+    storage.put(corrupt, b"not zlib").unwrap();
+    let (missing, _) = loose(&blob(b"missing"));
+    let (dangling, data) = loose(&blob(b"dangling"));
+    storage.put(dangling, &data).unwrap();
+
+    let (broken_tree, data) = loose(&tree(&[
+      ("corrupt", corrupt),
+      ("good", good),
+      ("missing", missing),
+      ("swapped", swapped),
+    ]));
+    storage.put(broken_tree, &data).unwrap();
+    let (good_tree, data) = loose(&tree(&[("good", good)]));
+    storage.put(good_tree, &data).unwrap();
+
+    let mut repository =
+      BundledRepository::with_storage(Box::new(storage), ObjectFormat::Sha1).unwrap();
+    set_root(&mut repository, "github.com@a:broken", broken_tree);
+    set_root(&mut repository, "github.com@b:good", good_tree);
+    let actual = repository.fsck().unwrap();
+
+    let broken = &actual.namespaces["github.com@a:broken"];
+    assert_eq!(broken.checked_objects, 4);
+    assert_eq!(broken.problems.len(), 3);
+    assert!(broken.problems.contains(&FsckProblem::Missing {
+      oid: missing,
+      referrer: Some(broken_tree),
+    }));
+    assert!(broken.problems.contains(&FsckProblem::HashMismatch {
+      oid: swapped,
+      actual: actual_oid,
+    }));
+    assert!(broken
+      .problems
+      .iter()
+      .any(|v| matches!(v, FsckProblem::Corrupt { oid, .. } if *oid == corrupt)));
+
+    let good_namespace = &actual.namespaces["github.com@b:good"];
+    assert_eq!(good_namespace.checked_objects, 2);
+    assert!(good_namespace.problems.is_empty());
+    assert_eq!(actual.dangling, vec![dangling]);
+    assert!(!actual.is_ok());
+  }
+
+  #[test]
+  fn test_build_when_ref_target_is_missing() {
+    let mut repository = BundledRepository::new().unwrap();
+    set_root(
+      &mut repository,
+      "github.com@a:repo",
+      ObjectId::zero(ObjectFormat::Sha1),
+    );
+    let actual = repository.fsck().unwrap();
+
+    assert_eq!(
+      actual.namespaces["github.com@a:repo"].problems,
+      vec![FsckProblem::Missing {
+        oid: ObjectId::zero(ObjectFormat::Sha1),
+        referrer: None,
+      }]
+    );
+  }
+
+  #[test]
+  fn test_build_when_fragment_is_invalid() {
+    let dir = temp_dir("fsck-invalid-fragment");
+    let path = dir.join("bundle");
+    let mut repository = BundledRepository::create(&path).unwrap();
+    let oid = repository.insert(&blob(b"kept")).unwrap();
+    set_root(&mut repository, "github.com@a:repo", oid);
+    drop(repository);
+    let mut refs = fs::read_to_string(path.join("refs")).unwrap();
+    refs.push_str(&format!("{} not-a-fragment refs/heads/master\n", oid));
+    fs::write(path.join("refs"), &refs).unwrap();
+
+    let mut repository = BundledRepository::open(&path).unwrap();
+    let actual = repository.fsck().unwrap();
+    let namespace = &actual.namespaces["not-a-fragment"];
+
+    assert_eq!(
+      namespace.problems,
+      vec![FsckProblem::InvalidFragment {
+        reason: Error::MalformedFragment("not-a-fragment".to_owned()).to_string(),
+      }]
+    );
+    assert_eq!(namespace.checked_objects, 1);
+    assert!(actual.dangling.is_empty());
+    assert!(!actual.is_ok());
+
+    // The line survives other ref updates.
+    set_root(&mut repository, "github.com@b:repo", oid);
+    assert_eq!(
+      fs::read_to_string(path.join("refs"))
+        .unwrap()
+        .lines()
+        .count(),
+      3
+    );
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn test_build_empty_repository() {
+    let repository = BundledRepository::new().unwrap();
+    let actual = repository.fsck().unwrap();
+
+    assert!(actual.is_ok());
+    assert!(actual.dangling.is_empty());
+  }
+}
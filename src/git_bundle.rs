@@ -0,0 +1,576 @@
+use super::bundled_repository::{BundledRepository, ImportReport};
+use super::error::Error;
+use super::git_object::GitObject;
+use super::object_format::{ObjectFormat, ObjectId};
+use super::object_header::parse_hex_oid;
+use super::pack_file::PackFile;
+use super::pack_writer::PackWriter;
+use super::path_fragment::PathFragment;
+use super::ref_transaction::check_ref_name;
+use std::collections::{BTreeMap, HashSet};
+
+const V2_SIGNATURE: &[u8] = b"# v2 git bundle\n";
+const V3_SIGNATURE: &[u8] = b"# v3 git bundle\n";
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct GitBundleHeader {
+  pub version: u32,
+  // Version 2 bundles are always SHA-1; version 3 ones declare their format.
+  pub object_format: ObjectFormat,
+  pub prerequisites: Vec<ObjectId>,
+  pub refs: BTreeMap<String, ObjectId>,
+}
+
+impl GitBundleHeader {
+  // Returns the header and the position where the pack starts.
+  pub fn parse(data: &[u8]) -> Result<(GitBundleHeader, usize), Error> {
+    let version = if data.starts_with(V2_SIGNATURE) {
+      2
+    } else if data.starts_with(V3_SIGNATURE) {
+      3
+    } else {
+      return Err(malformed("invalid signature"));
+    };
+
+    let mut header = GitBundleHeader {
+      version,
+      object_format: ObjectFormat::Sha1,
+      prerequisites: Vec::new(),
+      refs: BTreeMap::new(),
+    };
+    let mut pos = V2_SIGNATURE.len();
+    loop {
+      let end = match data[pos..].iter().position(|&v| v == b'\n') {
+        Some(v) => Ok(pos + v),
+        None => Err(malformed("unterminated header")),
+      }?;
+      let line = &data[pos..end];
+      pos = end + 1;
+
+      match line.first() {
+        None => break,
+        Some(b'@') if version == 3 => header.object_format = parse_capability(&line[1..])?,
+        Some(b'-') => {
+          let oid = parse_oid(&line[1..], header.object_format)?;
+          header.prerequisites.push(oid);
+        }
+        Some(_) => {
+          let (oid, name) = match line.iter().position(|&v| v == b' ') {
+            Some(v) => Ok((&line[..v], &line[v + 1..])),
+            None => Err(malformed("invalid ref line")),
+          }?;
+          let name = match std::str::from_utf8(name) {
+            Ok(v) => Ok(v.to_owned()),
+            Err(_) => Err(Error::EncodingError),
+          }?;
+          header
+            .refs
+            .insert(name, parse_oid(oid, header.object_format)?);
+        }
+      }
+    }
+
+    Ok((header, pos))
+  }
+
+  pub fn to_bytes(&self) -> Vec<u8> {
+    let mut ret = match self.version {
+      3 => {
+        let mut ret = V3_SIGNATURE.to_vec();
+        ret.extend(format!("@object-format={}\n", self.object_format).into_bytes());
+        ret
+      }
+      _ => V2_SIGNATURE.to_vec(),
+    };
+    for oid in self.prerequisites.iter() {
+      ret.extend(format!("-{}\n", oid).into_bytes());
+    }
+    for (name, oid) in self.refs.iter() {
+      ret.extend(format!("{} {}\n", oid, name).into_bytes());
+    }
+    ret.push(b'\n');
+
+    ret
+  }
+}
+
+// Writes the oldest version that can carry the repository's object format.
+pub fn export(repository: &BundledRepository, fragment: &PathFragment) -> Result<Vec<u8>, Error> {
+  let version = match repository.object_format() {
+    ObjectFormat::Sha1 => 2,
+    _ => 3,
+  };
+
+  export_with_version(repository, fragment, version)
+}
+
+pub fn export_with_version(
+  repository: &BundledRepository,
+  fragment: &PathFragment,
+  version: u32,
+) -> Result<Vec<u8>, Error> {
+  let object_format = repository.object_format();
+  // Version 2 has no way to declare a format other than SHA-1.
+  match (version, object_format) {
+    (2, ObjectFormat::Sha1) | (3, _) => Ok(()),
+    _ => Err(Error::UnsupportedGitBundleVersion {
+      version,
+      format: object_format.to_string(),
+    }),
+  }?;
+  let refs = match repository.refs(fragment) {
+    Some(v) => Ok(v),
+    None => Err(Error::FragmentNotFound(fragment.to_string())),
+  }?;
+
+  let roots: Vec<ObjectId> = refs.values().cloned().collect();
+  let mut writer = PackWriter::with_object_format(true, object_format);
+  for oid in repository.reachable_oids(&roots)? {
+    match repository.find(&oid)? {
+      Some(object) => writer.add(GitObject::clone(&object)),
+      None => Err(Error::ObjectNotFound(oid.to_string())),
+    }?;
+  }
+
+  let header = GitBundleHeader {
+    version,
+    object_format,
+    prerequisites: Vec::new(),
+    refs,
+  };
+  let mut ret = header.to_bytes();
+  ret.extend(writer.write()?.pack);
+
+  Ok(ret)
+}
+
+pub fn import(
+  repository: &mut BundledRepository,
+  data: &[u8],
+  fragment: &PathFragment,
+) -> Result<ImportReport, Error> {
+  let (header, pack_start) = GitBundleHeader::parse(data)?;
+  if header.object_format != repository.object_format() {
+    return Err(Error::ObjectFormatMismatch {
+      expected: repository.object_format().name().to_owned(),
+      actual: header.object_format.name().to_owned(),
+    });
+  }
+  for oid in header.prerequisites.iter() {
+    if !repository.exists(oid)? {
+      return Err(Error::MissingPrerequisite(oid.to_string()));
+    }
+  }
+
+  // Incremental bundles carry thin packs whose deltas refer to objects that
+  // only the prerequisites provide.
+  let pack = PackFile::with_format(data[pack_start..].to_vec(), header.object_format)?;
+  let objects =
+    pack.objects_with(&|oid| Ok(repository.find(oid)?.map(|v| GitObject::clone(&v))))?;
+
+  // Nothing is stored until every ref is known to be valid and to resolve.
+  for name in header.refs.keys() {
+    check_ref_name(name)?;
+  }
+  let oids: HashSet<ObjectId> = objects.iter().map(|(oid, _)| *oid).collect();
+  for oid in header.refs.values() {
+    if !oids.contains(oid) && !repository.exists(oid)? {
+      return Err(Error::ObjectNotFound(oid.to_string()));
+    }
+  }
+
+  let mut report = ImportReport::default();
+  for (oid, object) in objects.iter() {
+    if repository.exists(oid)? {
+      report.shared_objects += 1;
+    } else {
+      repository.insert(object)?;
+      report.new_objects += 1;
+    }
+  }
+
+  // A bundle may carry only some of the fragment's refs, e.g. one branch, so
+  // the refs it does not mention are kept.
+  report.refs = header.refs.len();
+  repository.merge_refs(fragment, header.refs)?;
+
+  Ok(report)
+}
+
+fn parse_capability(capability: &[u8]) -> Result<ObjectFormat, Error> {
+  match capability {
+    v if v.starts_with(b"object-format=") => {
+      ObjectFormat::from_name(&String::from_utf8_lossy(&v[14..]))
+    }
+    v => Err(malformed(&format!(
+      "unsupported capability: {}",
+      String::from_utf8_lossy(v)
+    ))),
+  }
+}
+
+// Prerequisite lines may carry a comment after the oid.
+fn parse_oid(line: &[u8], format: ObjectFormat) -> Result<ObjectId, Error> {
+  let hex = match line.iter().position(|&v| v == b' ') {
+    Some(v) => &line[..v],
+    None => line,
+  };
+
+  match parse_hex_oid(hex, format) {
+    Some(v) => Ok(v),
+    None => Err(Error::InvalidObjectId(
+      String::from_utf8_lossy(hex).into_owned(),
+    )),
+  }
+}
+
+fn malformed(reason: &str) -> Error {
+  Error::MalformedGitBundle(reason.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_helper::{commit_file, git, temp_dir};
+  use std::fs;
+  use std::path::{Path, PathBuf};
+
+  fn origin(dir: &Path) -> PathBuf {
+    origin_with_format(dir, ObjectFormat::Sha1)
+  }
+
+  fn origin_with_format(dir: &Path, format: ObjectFormat) -> PathBuf {
+    let ret = dir.join("origin");
+    fs::create_dir_all(&ret).unwrap();
+    git(
+      &ret,
+      &["init", "-q", &format!("--object-format={}", format), "."],
+    );
+    for i in 0..5 {
+      let content = (0..200)
+        .map(|v| format!("line {} {}\n", v, if v == i { "changed" } else { "" }))
+        .collect::<String>();
+      commit_file(&ret, "file.txt", &content);
+    }
+    git(&ret, &["tag", "-a", "v1", "-m", "v1"]);
+
+    ret
+  }
+
+  fn sha1(hex: &str) -> ObjectId {
+    ObjectId::from_hex(ObjectFormat::Sha1, hex.trim()).unwrap()
+  }
+
+  fn fragment() -> PathFragment {
+    PathFragment::parse_full_qualified_fragment("github.com@tett23:ckusro-core").unwrap()
+  }
+
+  #[test]
+  fn test_export() {
+    let dir = temp_dir("export");
+    let origin = origin(&dir);
+    let mut repository = BundledRepository::new().unwrap();
+    repository.import(&origin, &fragment()).unwrap();
+    let bundle = dir.join("export.bundle");
+    fs::write(&bundle, export(&repository, &fragment()).unwrap()).unwrap();
+
+    git(
+      &origin,
+      &["bundle", "verify", "-q", bundle.to_str().unwrap()],
+    );
+    git(&dir, &["clone", "-q", bundle.to_str().unwrap(), "cloned"]);
+    let cloned = dir.join("cloned");
+
+    assert_eq!(
+      git(&cloned, &["rev-parse", "HEAD"]),
+      git(&origin, &["rev-parse", "HEAD"])
+    );
+    assert_eq!(
+      fs::read(cloned.join("file.txt")).unwrap(),
+      fs::read(origin.join("file.txt")).unwrap()
+    );
+    assert_eq!(git(&cloned, &["tag"]), "v1\n");
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn test_export_when_fragment_is_unknown() {
+    let repository = BundledRepository::new().unwrap();
+    let actual = export(&repository, &fragment());
+
+    assert_eq!(
+      actual.err(),
+      Some(Error::FragmentNotFound(
+        "github.com@tett23:ckusro-core".to_owned()
+      ))
+    );
+  }
+
+  #[test]
+  fn test_export_with_version() {
+    let dir = temp_dir("export-with-version");
+    let origin = origin(&dir);
+    let mut repository = BundledRepository::new().unwrap();
+    repository.import(&origin, &fragment()).unwrap();
+    let exported = export_with_version(&repository, &fragment(), 3).unwrap();
+    assert!(exported.starts_with(b"# v3 git bundle\n@object-format=sha1\n"));
+
+    let bundle = dir.join("export.bundle");
+    fs::write(&bundle, exported).unwrap();
+    git(
+      &origin,
+      &["bundle", "verify", "-q", bundle.to_str().unwrap()],
+    );
+
+    let repository = BundledRepository::with_object_format(ObjectFormat::Sha256).unwrap();
+    assert_eq!(
+      export_with_version(&repository, &fragment(), 2).err(),
+      Some(Error::UnsupportedGitBundleVersion {
+        version: 2,
+        format: "sha256".to_owned(),
+      })
+    );
+    assert!(matches!(
+      export_with_version(&repository, &fragment(), 4),
+      Err(Error::UnsupportedGitBundleVersion { version: 4, .. })
+    ));
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn test_import() {
+    let dir = temp_dir("import");
+    let origin = origin(&dir);
+
+    for version in &["2", "3"] {
+      let bundle = dir.join(format!("v{}.bundle", version));
+      git(
+        &origin,
+        &[
+          "bundle",
+          "create",
+          "-q",
+          &format!("--version={}", version),
+          bundle.to_str().unwrap(),
+          "--all",
+        ],
+      );
+
+      let mut repository = BundledRepository::new().unwrap();
+      let actual = import(&mut repository, &fs::read(&bundle).unwrap(), &fragment()).unwrap();
+      let expected = git(&origin, &["rev-list", "--objects", "--all"])
+        .lines()
+        .count();
+
+      assert_eq!(actual.new_objects, expected);
+      assert_eq!(actual.shared_objects, 0);
+      assert_eq!(
+        repository.refs(&fragment()).unwrap().get("refs/tags/v1"),
+        Some(&sha1(&git(&origin, &["rev-parse", "v1"])))
+      );
+    }
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn test_import_keeps_refs_missing_from_bundle() {
+    let dir = temp_dir("import-merge");
+    let origin = origin(&dir);
+    let bundle = dir.join("master.bundle");
+    git(&origin, &["branch", "topic", "HEAD~1"]);
+    git(
+      &origin,
+      &["bundle", "create", "-q", bundle.to_str().unwrap(), "HEAD"],
+    );
+    let mut repository = BundledRepository::new().unwrap();
+    repository.import(&origin, &fragment()).unwrap();
+    let expected = repository.refs(&fragment()).unwrap();
+
+    commit_file(&origin, "file.txt", "updated");
+    git(
+      &origin,
+      &["bundle", "create", "-q", bundle.to_str().unwrap(), "HEAD"],
+    );
+    import(&mut repository, &fs::read(&bundle).unwrap(), &fragment()).unwrap();
+    let actual = repository.refs(&fragment()).unwrap();
+
+    assert_eq!(
+      actual.get("HEAD"),
+      Some(&sha1(&git(&origin, &["rev-parse", "HEAD"])))
+    );
+    assert_eq!(
+      actual.get("refs/heads/topic"),
+      expected.get("refs/heads/topic")
+    );
+    assert_eq!(actual.get("refs/tags/v1"), expected.get("refs/tags/v1"));
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn test_import_when_ref_target_is_missing() {
+    let dir = temp_dir("import-missing-target");
+    let origin = origin(&dir);
+    let bundle = dir.join("origin.bundle");
+    git(
+      &origin,
+      &["bundle", "create", "-q", bundle.to_str().unwrap(), "HEAD"],
+    );
+    let mut data = fs::read(&bundle).unwrap();
+    let head = git(&origin, &["rev-parse", "HEAD"]);
+    let missing = "0123456789012345678901234567890123456789";
+    let pos = data
+      .windows(40)
+      .position(|v| v == head.trim().as_bytes())
+      .unwrap();
+    data[pos..pos + 40].copy_from_slice(missing.as_bytes());
+
+    let mut repository = BundledRepository::new().unwrap();
+    let actual = import(&mut repository, &data, &fragment());
+
+    assert_eq!(
+      actual.err(),
+      Some(Error::ObjectNotFound(missing.to_owned()))
+    );
+    assert_eq!(repository.oids(), Ok(Vec::new()));
+    assert_eq!(repository.refs(&fragment()), None);
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn test_import_when_ref_name_is_invalid() {
+    let dir = temp_dir("import-invalid-ref-name");
+    let origin = origin(&dir);
+    let bundle = dir.join("origin.bundle");
+    git(
+      &origin,
+      &["bundle", "create", "-q", bundle.to_str().unwrap(), "HEAD"],
+    );
+    let mut data = fs::read(&bundle).unwrap();
+    let pos = data.windows(6).position(|v| v == b" HEAD\n").unwrap();
+    data[pos + 1..pos + 5].copy_from_slice(b"H..D");
+
+    let mut repository = BundledRepository::new().unwrap();
+    let actual = import(&mut repository, &data, &fragment());
+
+    assert_eq!(actual.err(), Some(Error::InvalidRefName("H..D".to_owned())));
+    assert_eq!(repository.oids(), Ok(Vec::new()));
+    assert_eq!(repository.refs(&fragment()), None);
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn test_import_incremental_bundle() {
+    let dir = temp_dir("import-incremental");
+    let origin = origin(&dir);
+    let base = dir.join("base.bundle");
+    let incremental = dir.join("incremental.bundle");
+    git(&origin, &["branch", "base", "HEAD~2"]);
+    git(
+      &origin,
+      &["bundle", "create", "-q", base.to_str().unwrap(), "base"],
+    );
+    git(
+      &origin,
+      &[
+        "bundle",
+        "create",
+        "-q",
+        incremental.to_str().unwrap(),
+        "base..HEAD",
+      ],
+    );
+
+    let mut repository = BundledRepository::new().unwrap();
+    let data = fs::read(&incremental).unwrap();
+    let actual = import(&mut repository, &data, &fragment());
+    assert!(matches!(actual, Err(Error::MissingPrerequisite(_))));
+
+    import(&mut repository, &fs::read(&base).unwrap(), &fragment()).unwrap();
+    let actual = import(&mut repository, &data, &fragment()).unwrap();
+    let head = sha1(&git(&origin, &["rev-parse", "HEAD"]));
+
+    assert_eq!(actual.new_objects, 6);
+    assert_eq!(repository.reachable_oids(&[head]).unwrap().len(), 15);
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn test_import_and_export_in_sha256_format() {
+    let dir = temp_dir("sha256");
+    let origin = origin_with_format(&dir, ObjectFormat::Sha256);
+    let bundle = dir.join("origin.bundle");
+    git(
+      &origin,
+      &["bundle", "create", "-q", bundle.to_str().unwrap(), "--all"],
+    );
+
+    let mut repository = BundledRepository::new().unwrap();
+    let data = fs::read(&bundle).unwrap();
+    assert_eq!(
+      import(&mut repository, &data, &fragment()).err(),
+      Some(Error::ObjectFormatMismatch {
+        expected: "sha1".to_owned(),
+        actual: "sha256".to_owned(),
+      })
+    );
+
+    let mut repository = BundledRepository::with_object_format(ObjectFormat::Sha256).unwrap();
+    let actual = import(&mut repository, &data, &fragment()).unwrap();
+    let head = git(&origin, &["rev-parse", "HEAD"]);
+    assert_eq!(
+      actual.new_objects,
+      git(&origin, &["rev-list", "--objects", "--all"])
+        .lines()
+        .count()
+    );
+    assert_eq!(
+      repository.refs(&fragment()).unwrap().get("HEAD"),
+      Some(&ObjectId::from_hex(ObjectFormat::Sha256, head.trim()).unwrap())
+    );
+
+    let exported = export(&repository, &fragment()).unwrap();
+    assert!(exported.starts_with(b"# v3 git bundle\n@object-format=sha256\n"));
+    let bundle = dir.join("export.bundle");
+    fs::write(&bundle, exported).unwrap();
+    git(&dir, &["clone", "-q", bundle.to_str().unwrap(), "cloned"]);
+    assert_eq!(git(&dir.join("cloned"), &["rev-parse", "HEAD"]), head);
+
+    let _ = fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn test_header_parse() {
+    let oid = "0123456789012345678901234567890123456789";
+    let data = format!(
+      "# v3 git bundle\n@object-format=sha1\n-{} comment\n{} refs/heads/master\n\nPACK",
+      oid, oid
+    );
+    let (actual, pos) = GitBundleHeader::parse(data.as_bytes()).unwrap();
+    let oid = sha1(oid);
+
+    assert_eq!(actual.version, 3);
+    assert_eq!(actual.prerequisites, vec![oid]);
+    assert_eq!(actual.refs.get("refs/heads/master"), Some(&oid));
+    assert_eq!(&data.as_bytes()[pos..], b"PACK");
+  }
+
+  #[test]
+  fn test_header_parse_when_capability_is_unsupported() {
+    let data = b"# v3 git bundle\n@filter=blob:none\n\n";
+    let actual = GitBundleHeader::parse(data);
+
+    assert_eq!(
+      actual.err(),
+      Some(Error::MalformedGitBundle(
+        "unsupported capability: filter=blob:none".to_owned()
+      ))
+    );
+  }
+}
@@ -0,0 +1,424 @@
+use super::error::Error;
+use super::git_object::GitObject;
+use super::object_format::{ObjectFormat, ObjectId};
+use super::pack_file::PackFile;
+use super::pack_index::PackIndex;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+// Reads refs and objects straight from a repository on disk. libgit2 refuses
+// repositories with `core.repositoryformatversion = 1`, which every SHA-256
+// repository has, so imports cannot go through it.
+pub struct GitDirectory {
+  git_dir: PathBuf,
+  // Shared by every worktree; holds the objects, refs and config.
+  common_dir: PathBuf,
+  object_format: ObjectFormat,
+  object_dirs: Vec<PathBuf>,
+  packs: Vec<Pack>,
+}
+
+// Only the index is read up front; the pack itself is loaded the first time
+// an object is looked up in it.
+struct Pack {
+  index: PackIndex,
+  path: PathBuf,
+  file: OnceLock<PackFile>,
+}
+
+impl Pack {
+  fn file(&self, format: ObjectFormat) -> Result<&PackFile, Error> {
+    if let Some(v) = self.file.get() {
+      return Ok(v);
+    }
+
+    let pack = PackFile::with_format(access_result(fs::read(&self.path))?, format)?;
+    Ok(self.file.get_or_init(|| pack))
+  }
+}
+
+impl GitDirectory {
+  // `path` may be a worktree, a `.git` directory or a bare repository.
+  pub fn open(path: &Path) -> Result<GitDirectory, Error> {
+    let git_dir = find_git_dir(path)?;
+    let common_dir = match fs::read_to_string(git_dir.join("commondir")) {
+      Ok(v) => git_dir.join(v.trim()),
+      Err(_) => git_dir.clone(),
+    };
+    let object_format = read_object_format(&common_dir)?;
+    let object_dirs = read_object_dirs(&common_dir.join("objects"))?;
+
+    let mut packs: Vec<Pack> = Vec::new();
+    for dir in object_dirs.iter() {
+      packs.extend(read_packs(&dir.join("pack"), object_format)?);
+    }
+
+    Ok(GitDirectory {
+      git_dir,
+      common_dir,
+      object_format,
+      object_dirs,
+      packs,
+    })
+  }
+
+  pub fn path(&self) -> &Path {
+    &self.git_dir
+  }
+
+  pub fn object_format(&self) -> ObjectFormat {
+    self.object_format
+  }
+
+  // Every direct ref under `refs/` plus HEAD. Other symbolic refs point at a
+  // ref that is listed on its own.
+  pub fn refs(&self) -> Result<BTreeMap<String, ObjectId>, Error> {
+    let mut ret: BTreeMap<String, ObjectId> = BTreeMap::new();
+    if let Some(content) = read_optional(&self.common_dir.join("packed-refs"))? {
+      for line in content.lines() {
+        if line.is_empty() || line.starts_with('#') || line.starts_with('^') {
+          continue;
+        }
+        match line.split_once(' ') {
+          Some((oid, name)) => ret.insert(name.to_owned(), self.parse_oid(oid)?),
+          None => return Err(access_error(&format!("invalid packed ref: {}", line))),
+        };
+      }
+    }
+    // Loose refs take precedence over packed ones.
+    self.read_loose_refs(&self.common_dir.join("refs"), "refs", &mut ret)?;
+
+    // An unborn HEAD has nothing to point at.
+    if let Some(head) = read_optional(&self.git_dir.join("HEAD"))? {
+      let head = match head.trim().strip_prefix("ref: ") {
+        Some(name) => ret.get(name).cloned(),
+        None => Some(self.parse_oid(head.trim())?),
+      };
+      if let Some(oid) = head {
+        ret.insert("HEAD".to_owned(), oid);
+      }
+    }
+
+    Ok(ret)
+  }
+
+  pub fn read(&self, oid: &ObjectId) -> Result<Option<GitObject>, Error> {
+    let hex = oid.to_string();
+    for dir in self.object_dirs.iter() {
+      match fs::read(dir.join(&hex[..2]).join(&hex[2..])) {
+        Ok(data) => return GitObject::from_u8_vec_with_oid(&data, oid).map(Some),
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => continue,
+        Err(err) => return Err(access_error(&err.to_string())),
+      }
+    }
+
+    for pack in self.packs.iter() {
+      if pack.index.find(oid).is_none() {
+        continue;
+      }
+      if let Some(object) = pack
+        .file(self.object_format)?
+        .find_object(&pack.index, oid)?
+      {
+        return Ok(Some(object));
+      }
+    }
+
+    Ok(None)
+  }
+
+  fn read_loose_refs(
+    &self,
+    dir: &Path,
+    prefix: &str,
+    refs: &mut BTreeMap<String, ObjectId>,
+  ) -> Result<(), Error> {
+    let entries = match fs::read_dir(dir) {
+      Ok(v) => v,
+      Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+      Err(err) => return Err(access_error(&err.to_string())),
+    };
+
+    for entry in entries {
+      let path = access_result(entry)?.path();
+      let name = match path.file_name() {
+        Some(v) => format!("{}/{}", prefix, v.to_string_lossy()),
+        None => continue,
+      };
+      if path.is_dir() {
+        self.read_loose_refs(&path, &name, refs)?;
+        continue;
+      }
+
+      let content = access_result(fs::read_to_string(&path))?;
+      if !content.starts_with("ref: ") {
+        refs.insert(name, self.parse_oid(content.trim())?);
+      }
+    }
+
+    Ok(())
+  }
+
+  fn parse_oid(&self, hex: &str) -> Result<ObjectId, Error> {
+    ObjectId::from_hex(self.object_format, hex)
+  }
+}
+
+fn find_git_dir(path: &Path) -> Result<PathBuf, Error> {
+  let dot_git = path.join(".git");
+  if dot_git.is_dir() {
+    return Ok(dot_git);
+  }
+  // Linked worktrees and submodules point at their git directory.
+  if dot_git.is_file() {
+    let content = access_result(fs::read_to_string(&dot_git))?;
+    return match content.trim().strip_prefix("gitdir: ") {
+      Some(v) => Ok(path.join(v)),
+      None => Err(access_error(&format!(
+        "invalid gitdir file: {}",
+        dot_git.display()
+      ))),
+    };
+  }
+  if path.join("HEAD").is_file() && path.join("objects").is_dir() {
+    return Ok(path.to_path_buf());
+  }
+
+  Err(access_error(&format!(
+    "not a git repository: {}",
+    path.display()
+  )))
+}
+
+fn read_object_format(common_dir: &Path) -> Result<ObjectFormat, Error> {
+  let config = access_result(fs::read_to_string(common_dir.join("config")))?;
+  let mut section = String::new();
+  for line in config.lines() {
+    let line = line.trim();
+    if line.starts_with('[') {
+      section = line
+        .trim_matches(|v| v == '[' || v == ']')
+        .trim()
+        .to_lowercase();
+      continue;
+    }
+
+    if let Some((key, value)) = line.split_once('=') {
+      if section == "extensions" && key.trim().eq_ignore_ascii_case("objectformat") {
+        return ObjectFormat::from_name(&value.trim().trim_matches('"').to_lowercase());
+      }
+    }
+  }
+
+  Ok(ObjectFormat::Sha1)
+}
+
+// The repository's own object directory followed by its alternates.
+fn read_object_dirs(objects: &Path) -> Result<Vec<PathBuf>, Error> {
+  let mut ret = vec![objects.to_path_buf()];
+  if let Some(content) = read_optional(&objects.join("info").join("alternates"))? {
+    for line in content.lines() {
+      let line = line.trim();
+      if !line.is_empty() && !line.starts_with('#') {
+        ret.push(objects.join(line));
+      }
+    }
+  }
+
+  Ok(ret)
+}
+
+fn read_packs(dir: &Path, format: ObjectFormat) -> Result<Vec<Pack>, Error> {
+  let entries = match fs::read_dir(dir) {
+    Ok(v) => v,
+    Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+    Err(err) => return Err(access_error(&err.to_string())),
+  };
+
+  let mut ret: Vec<Pack> = Vec::new();
+  for entry in entries {
+    let path = access_result(entry)?.path();
+    if path.extension() != Some("idx".as_ref()) {
+      continue;
+    }
+
+    let index = PackIndex::parse_with_format(&access_result(fs::read(&path))?, format)?;
+    ret.push(Pack {
+      index,
+      path: path.with_extension("pack"),
+      file: OnceLock::new(),
+    });
+  }
+
+  Ok(ret)
+}
+
+fn read_optional(path: &Path) -> Result<Option<String>, Error> {
+  match fs::read_to_string(path) {
+    Ok(v) => Ok(Some(v)),
+    Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+    Err(err) => Err(access_error(&err.to_string())),
+  }
+}
+
+fn access_result<T>(result: io::Result<T>) -> Result<T, Error> {
+  match result {
+    Ok(v) => Ok(v),
+    Err(err) => Err(access_error(&err.to_string())),
+  }
+}
+
+fn access_error(detail: &str) -> Error {
+  Error::RepositoryAccessFailed {
+    detail: detail.to_owned(),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_helper::{commit_file, git, temp_dir};
+
+  // Packed objects and refs from a first commit, loose ones from a second.
+  fn origin(dir: &Path, format: ObjectFormat) -> PathBuf {
+    let ret = dir.join("origin");
+    fs::create_dir_all(&ret).unwrap();
+    git(
+      &ret,
+      &["init", "-q", &format!("--object-format={}", format), "."],
+    );
+    commit_file(&ret, "a.txt", "a");
+    git(&ret, &["tag", "-a", "v1", "-m", "v1"]);
+    git(&ret, &["gc", "-q"]);
+    commit_file(&ret, "b.txt", "b");
+    git(&ret, &["branch", "topic"]);
+
+    ret
+  }
+
+  fn expected_refs(dir: &Path, format: ObjectFormat) -> BTreeMap<String, ObjectId> {
+    git(dir, &["show-ref", "--head"])
+      .lines()
+      .map(|line| {
+        let (oid, name) = line.split_once(' ').unwrap();
+        (name.to_owned(), ObjectId::from_hex(format, oid).unwrap())
+      })
+      .collect()
+  }
+
+  mod git_directory {
+    use super::*;
+
+    #[test]
+    fn test_open() {
+      for format in &[ObjectFormat::Sha1, ObjectFormat::Sha256] {
+        let dir = temp_dir(&format!("open-{}", format));
+        let origin = origin(&dir, *format);
+        let actual = GitDirectory::open(&origin).unwrap();
+
+        assert_eq!(actual.object_format(), *format);
+        assert_eq!(actual.path(), origin.join(".git").as_path());
+        assert_eq!(actual.refs(), Ok(expected_refs(&origin, *format)));
+        for line in git(&origin, &["rev-list", "--objects", "--all"]).lines() {
+          let oid = ObjectId::from_hex(*format, &line[..format.hex_length()]).unwrap();
+          let object = actual.read(&oid).unwrap().unwrap();
+
+          assert_eq!(object.object_id(*format), oid);
+        }
+        assert_eq!(actual.read(&ObjectId::zero(*format)), Ok(None));
+
+        let _ = fs::remove_dir_all(&dir);
+      }
+    }
+
+    #[test]
+    fn test_open_worktree() {
+      let dir = temp_dir("worktree");
+      let origin = origin(&dir, ObjectFormat::Sha1);
+      let worktree = dir.join("worktree");
+      git(
+        &origin,
+        &["worktree", "add", "-q", worktree.to_str().unwrap(), "topic"],
+      );
+      commit_file(&worktree, "c.txt", "c");
+      let actual = GitDirectory::open(&worktree).unwrap();
+      let head = git(&worktree, &["rev-parse", "HEAD"]);
+
+      assert_eq!(
+        actual.refs().unwrap().get("HEAD"),
+        Some(&ObjectId::from_hex(ObjectFormat::Sha1, head.trim()).unwrap())
+      );
+      assert!(actual
+        .read(&ObjectId::from_hex(ObjectFormat::Sha1, head.trim()).unwrap())
+        .unwrap()
+        .is_some());
+
+      let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_open_bare_repository() {
+      let dir = temp_dir("bare");
+      let origin = origin(&dir, ObjectFormat::Sha1);
+      let bare = dir.join("bare.git");
+      git(
+        &dir,
+        &[
+          "clone",
+          "-q",
+          "--bare",
+          origin.to_str().unwrap(),
+          bare.to_str().unwrap(),
+        ],
+      );
+      let actual = GitDirectory::open(&bare).unwrap();
+
+      assert_eq!(actual.refs(), Ok(expected_refs(&bare, ObjectFormat::Sha1)));
+
+      let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_read_when_loose_object_is_stored_under_other_id() {
+      let dir = temp_dir("mislabelled");
+      let origin = origin(&dir, ObjectFormat::Sha1);
+      let blob = git(&origin, &["rev-parse", "HEAD:b.txt"]);
+      let blob = blob.trim();
+      let other = ObjectId::from_hex(ObjectFormat::Sha1, &"f".repeat(40)).unwrap();
+      let objects = origin.join(".git").join("objects");
+      fs::create_dir_all(objects.join("ff")).unwrap();
+      fs::copy(
+        objects.join(&blob[..2]).join(&blob[2..]),
+        objects.join("ff").join("f".repeat(38)),
+      )
+      .unwrap();
+      let actual = GitDirectory::open(&origin).unwrap().read(&other);
+
+      assert_eq!(
+        actual,
+        Err(Error::ObjectIdMismatch {
+          expected: other.to_string(),
+          actual: blob.to_owned(),
+        })
+      );
+
+      let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_open_when_path_is_not_repository() {
+      let dir = temp_dir("not-repository");
+      let actual = GitDirectory::open(&dir);
+
+      assert!(matches!(
+        actual.err(),
+        Some(Error::RepositoryAccessFailed { .. })
+      ));
+
+      let _ = fs::remove_dir_all(&dir);
+    }
+  }
+}
@@ -1,9 +1,12 @@
-use super::compressed_git_object::CompressedGitObject;
+use super::compressed_git_object::{self, CompressedGitObject};
 use super::error::Error;
-use git2::ObjectType;
+use super::object_format::{ObjectFormat, ObjectId};
+use super::parse_mode::ParseMode;
+use flate2::Compression;
+use git2::{ObjectType, Oid};
 use std::fmt;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct GitObject {
   object_type: ObjectType,
   length: u64,
@@ -20,11 +23,62 @@ impl GitObject {
   }
 
   pub fn from_u8_vec(data: &Vec<u8>) -> Result<GitObject, Error> {
+    GitObject::from_u8_vec_with_mode(data, ParseMode::Strict)
+  }
+
+  pub fn from_u8_vec_with_mode(data: &Vec<u8>, mode: ParseMode) -> Result<GitObject, Error> {
     let compressed = CompressedGitObject::new(data);
-    let (object_type, length, content) = compressed.parse()?;
+    let (object_type, length, content) = compressed.parse_with_mode(mode)?;
 
     Ok(GitObject::new(object_type, length, &content))
   }
+
+  // Decodes the object and checks that it hashes to `oid` in the format of `oid`.
+  pub fn from_u8_vec_with_oid(data: &Vec<u8>, oid: &ObjectId) -> Result<GitObject, Error> {
+    let object = GitObject::from_u8_vec(data)?;
+    let actual = object.object_id(oid.format());
+
+    if &actual != oid {
+      return Err(Error::ObjectIdMismatch {
+        expected: oid.to_string(),
+        actual: actual.to_string(),
+      });
+    }
+
+    Ok(object)
+  }
+
+  // Git hashes `<type> <content length>\0<content>`; the recorded length is
+  // not trusted because lenient parsing keeps it even when it is wrong.
+  pub fn object_id(&self, format: ObjectFormat) -> ObjectId {
+    let header = format!("{} {}\0", self.object_type.str(), self.content.len());
+
+    format.hash_chunks(&[header.as_bytes(), &self.content])
+  }
+
+  pub fn oid(&self) -> Result<Oid, Error> {
+    self.object_id(ObjectFormat::Sha1).to_oid()
+  }
+
+  pub fn to_u8_vec(&self) -> Result<Vec<u8>, Error> {
+    self.to_u8_vec_with_compression(Compression::default())
+  }
+
+  pub fn to_u8_vec_with_compression(&self, level: Compression) -> Result<Vec<u8>, Error> {
+    compressed_git_object::build(self.object_type, self.length, &self.content, level)
+  }
+
+  pub fn object_type(&self) -> ObjectType {
+    self.object_type
+  }
+
+  pub fn length(&self) -> u64 {
+    self.length
+  }
+
+  pub fn content(&self) -> &Vec<u8> {
+    &self.content
+  }
 }
 
 impl fmt::Display for GitObject {
@@ -37,7 +91,6 @@ impl fmt::Display for GitObject {
 mod tests {
   use super::*;
   use flate2::write::ZlibEncoder;
-  use flate2::Compression;
   use std::io::prelude::*;
 
   fn deflate(data: &[u8]) -> Vec<u8> {
@@ -55,4 +108,112 @@ mod tests {
 
     assert_eq!(actual, Ok(expected))
   }
+
+  #[test]
+  fn git_object_from_u8_vec_with_mode() {
+    let bytes = deflate(b"blob 3\0a");
+    let strict = GitObject::from_u8_vec(&bytes);
+    let lenient = GitObject::from_u8_vec_with_mode(&bytes, ParseMode::Lenient);
+
+    assert!(strict.is_err());
+    assert_eq!(
+      lenient,
+      Ok(GitObject::new(ObjectType::Blob, 3, &b"a".to_vec()))
+    )
+  }
+
+  #[test]
+  fn git_object_to_u8_vec() {
+    let object = GitObject::new(ObjectType::Blob, 1, &b"a".to_vec());
+    let actual = object.to_u8_vec();
+
+    assert_eq!(actual, Ok(deflate(b"blob 1\0a")))
+  }
+
+  #[test]
+  fn git_object_to_u8_vec_round_trip() {
+    let object = GitObject::new(ObjectType::Tree, 0, &Vec::new());
+    let levels = vec![
+      Compression::none(),
+      Compression::fast(),
+      Compression::best(),
+    ];
+
+    for level in levels {
+      let bytes = object.to_u8_vec_with_compression(level).unwrap();
+      let actual = GitObject::from_u8_vec(&bytes);
+
+      assert_eq!(actual, Ok(GitObject::new(ObjectType::Tree, 0, &Vec::new())))
+    }
+  }
+
+  #[test]
+  fn git_object_oid() {
+    let object = GitObject::new(ObjectType::Blob, 1, &b"a".to_vec());
+    let actual = object.oid();
+    let expected = Oid::from_str("2e65efe2a145dda7ee51d1741299f848e5bf752e").unwrap();
+
+    assert_eq!(actual, Ok(expected))
+  }
+
+  #[test]
+  fn git_object_from_u8_vec_with_oid() {
+    let bytes = deflate(b"blob 1\0a");
+    let oid = Oid::from_str("2e65efe2a145dda7ee51d1741299f848e5bf752e").unwrap();
+    let actual = GitObject::from_u8_vec_with_oid(&bytes, &oid.into());
+    let expected = GitObject::new(ObjectType::Blob, 1, &b"a".to_vec());
+
+    assert_eq!(actual, Ok(expected))
+  }
+
+  #[test]
+  fn git_object_from_u8_vec_with_oid_when_oid_does_not_match() {
+    let bytes = deflate(b"blob 1\0b");
+    let oid = Oid::from_str("2e65efe2a145dda7ee51d1741299f848e5bf752e").unwrap();
+    let actual = GitObject::from_u8_vec_with_oid(&bytes, &oid.into());
+    let expected = Error::ObjectIdMismatch {
+      expected: "2e65efe2a145dda7ee51d1741299f848e5bf752e".to_owned(),
+      actual: "63d8dbd40c23542e740659a7168a0ce3138ea748".to_owned(),
+    };
+
+    assert_eq!(actual, Err(expected))
+  }
+
+  #[test]
+  fn git_object_oid_matches_libgit2() {
+    let data: Vec<(ObjectType, &[u8])> = vec![
+      (ObjectType::Blob, b""),
+      (ObjectType::Blob, b"hello\n"),
+      (ObjectType::Tree, b""),
+      (
+        ObjectType::Commit,
+        b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\n",
+      ),
+    ];
+
+    for (object_type, content) in data {
+      let object = GitObject::new(object_type, content.len() as u64, &content.to_vec());
+      let expected = Oid::hash_object(object_type, content).unwrap();
+
+      assert_eq!(object.oid(), Ok(expected));
+    }
+  }
+
+  #[test]
+  fn git_object_from_u8_vec_with_oid_in_sha256_format() {
+    let bytes = deflate(b"blob 1\0a");
+    let sha256 = "eb337bcee2061c5313c9a1392116b6c76039e9e30d71467ae359b36277e17dc7";
+    let oid = ObjectId::from_hex(ObjectFormat::Sha256, sha256).unwrap();
+    let actual = GitObject::from_u8_vec_with_oid(&bytes, &oid);
+
+    assert_eq!(
+      actual,
+      Ok(GitObject::new(ObjectType::Blob, 1, &b"a".to_vec()))
+    );
+
+    let bytes = deflate(b"blob 1\0b");
+    let actual = GitObject::from_u8_vec_with_oid(&bytes, &oid);
+
+    assert!(matches!(actual, Err(Error::ObjectIdMismatch { .. })));
+  }
 }
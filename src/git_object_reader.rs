@@ -0,0 +1,153 @@
+use super::compressed_git_object::read_header;
+use super::error::Error;
+use flate2::read::ZlibDecoder;
+use git2::ObjectType;
+use std::cmp;
+use std::io;
+use std::io::prelude::*;
+
+pub struct GitObjectReader<R: Read> {
+  decoder: ZlibDecoder<R>,
+  object_type: ObjectType,
+  length: u64,
+  position: u64,
+}
+
+impl<R: Read> GitObjectReader<R> {
+  pub fn new(reader: R, limit: u64) -> Result<GitObjectReader<R>, Error> {
+    let mut decoder = ZlibDecoder::new(reader);
+    let (object_type, length) = read_header(&mut decoder)?;
+
+    if length > limit {
+      return Err(Error::ObjectTooLarge { length, limit });
+    }
+
+    Ok(GitObjectReader {
+      decoder,
+      object_type,
+      length,
+      position: 0,
+    })
+  }
+
+  pub fn object_type(&self) -> ObjectType {
+    self.object_type
+  }
+
+  pub fn length(&self) -> u64 {
+    self.length
+  }
+}
+
+impl<R: Read> Read for GitObjectReader<R> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    if buf.is_empty() {
+      return Ok(0);
+    }
+
+    // Ask for one byte past the declared length so that oversized bodies are
+    // detected without inflating them any further.
+    let remaining = self.length.saturating_sub(self.position);
+    let size = cmp::min(buf.len() as u64, remaining.saturating_add(1)) as usize;
+    let read = self.decoder.read(&mut buf[..size])?;
+    self.position += read as u64;
+
+    if self.position > self.length {
+      return Err(length_error(self.length, self.position));
+    }
+    if read == 0 && self.position < self.length {
+      return Err(length_error(self.length, self.position));
+    }
+
+    Ok(read)
+  }
+}
+
+fn length_error(expected: u64, actual: u64) -> io::Error {
+  let err = Error::LengthMismatch { expected, actual };
+
+  io::Error::new(io::ErrorKind::InvalidData, err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use flate2::write::ZlibEncoder;
+  use flate2::Compression;
+
+  fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data).unwrap();
+
+    encoder.finish().unwrap()
+  }
+
+  mod git_object_reader {
+    use super::*;
+
+    #[test]
+    fn test_read() {
+      let bytes = deflate(b"blob 5\0hello");
+      let mut reader = GitObjectReader::new(&bytes[..], 1024).unwrap();
+      let mut actual = Vec::new();
+      reader.read_to_end(&mut actual).unwrap();
+
+      assert_eq!(reader.object_type(), ObjectType::Blob);
+      assert_eq!(reader.length(), 5);
+      assert_eq!(actual, b"hello".to_vec());
+    }
+
+    #[test]
+    fn test_read_large_blob_in_chunks() {
+      let length = 4 * 1024 * 1024;
+      let mut data = format!("blob {}\0", length).into_bytes();
+      data.resize(data.len() + length, b'x');
+      let bytes = deflate(&data);
+      let mut reader = GitObjectReader::new(&bytes[..], length as u64).unwrap();
+      let actual = io::copy(&mut reader, &mut io::sink()).unwrap();
+
+      assert_eq!(actual, length as u64);
+    }
+
+    #[test]
+    fn test_new_when_object_exceeds_limit() {
+      let bytes = deflate(b"blob 5\0hello");
+      let actual = GitObjectReader::new(&bytes[..], 4);
+
+      assert_eq!(
+        actual.err(),
+        Some(Error::ObjectTooLarge {
+          length: 5,
+          limit: 4
+        })
+      );
+    }
+
+    #[test]
+    fn test_read_when_length_is_max() {
+      let bytes = deflate(format!("blob {}\0hello", u64::MAX).as_bytes());
+      let mut reader = GitObjectReader::new(&bytes[..], u64::MAX).unwrap();
+      let actual = reader.read_to_end(&mut Vec::new());
+
+      assert_eq!(actual.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_read_when_body_is_longer_than_header() {
+      let bytes = deflate(b"blob 2\0hello");
+      let mut reader = GitObjectReader::new(&bytes[..], 1024).unwrap();
+      let actual = reader.read_to_end(&mut Vec::new());
+
+      assert_eq!(actual.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_read_when_body_is_shorter_than_header() {
+      let bytes = deflate(b"blob 9\0hello");
+      let mut reader = GitObjectReader::new(&bytes[..], 1024).unwrap();
+      let actual = reader.read_to_end(&mut Vec::new());
+
+      assert_eq!(actual.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+  }
+}
@@ -1,12 +1,38 @@
+pub mod async_bundled_repository;
+pub mod blocking_task;
 pub mod bundled_repository;
+pub mod commit;
 pub mod compressed_git_object;
+pub mod dedup_report;
+pub mod delta;
 pub mod error;
+pub mod fsck_report;
+pub mod git_bundle;
+pub mod git_directory;
 pub mod git_object;
+pub mod git_object_reader;
+pub mod loose_object_storage;
 pub mod namespace;
 pub mod namespace_ref;
 pub mod namespace_ref_manager;
+pub mod object_cache;
+pub mod object_format;
+pub mod object_header;
+pub mod pack_file;
+pub mod pack_index;
+pub mod pack_writer;
+pub mod parse_mode;
 pub mod path_fragment;
+pub mod ref_transaction;
+pub mod shared_bundled_repository;
+pub mod signature;
+pub mod single_file_storage;
+pub mod storage;
+pub mod tag;
+#[cfg(test)]
+mod test_helper;
 pub mod tree;
+pub mod tree_object;
 
 pub fn hoge() -> String {
   // let obj = GitObject::new(&"".as_bytes().to_vec());
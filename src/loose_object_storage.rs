@@ -0,0 +1,203 @@
+use super::error::Error;
+use super::object_format::{ObjectFormat, ObjectId};
+use super::storage::{io_result, Storage};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::SystemTime;
+
+static TEMP_FILES: AtomicUsize = AtomicUsize::new(0);
+
+// Lays objects out like git's `objects` directory: `ab/cdef...`.
+#[derive(Debug)]
+pub struct LooseObjectStorage {
+  path: PathBuf,
+  object_format: ObjectFormat,
+}
+
+impl LooseObjectStorage {
+  pub fn new(path: &Path, object_format: ObjectFormat) -> Result<LooseObjectStorage, Error> {
+    io_result(fs::create_dir_all(path))?;
+
+    Ok(LooseObjectStorage {
+      path: path.to_path_buf(),
+      object_format,
+    })
+  }
+
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  fn object_path(&self, oid: &ObjectId) -> PathBuf {
+    let hex = oid.to_string();
+
+    self.path.join(&hex[..2]).join(&hex[2..])
+  }
+}
+
+impl Storage for LooseObjectStorage {
+  fn get(&self, oid: &ObjectId) -> Result<Option<Vec<u8>>, Error> {
+    match fs::read(self.object_path(oid)) {
+      Ok(v) => Ok(Some(v)),
+      Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+      Err(err) => io_result(Err(err)),
+    }
+  }
+
+  fn put(&mut self, oid: ObjectId, data: &[u8]) -> Result<(), Error> {
+    let path = self.object_path(&oid);
+    if path.exists() {
+      return io_result(
+        fs::OpenOptions::new()
+          .write(true)
+          .open(&path)
+          .and_then(|file| file.set_modified(SystemTime::now())),
+      );
+    }
+
+    // Write to a temporary file first so readers never see a partial object.
+    let dir = path.parent().unwrap_or(&self.path);
+    // Named per writer so that concurrent puts of one oid never share a file.
+    let temp = dir.join(format!(
+      "tmp_{}_{}",
+      std::process::id(),
+      TEMP_FILES.fetch_add(1, Ordering::SeqCst)
+    ));
+    let result = fs::create_dir_all(dir)
+      .and_then(|_| fs::write(&temp, data))
+      .and_then(|_| fs::rename(&temp, &path));
+    if result.is_err() {
+      let _ = fs::remove_file(&temp);
+    }
+
+    io_result(result)
+  }
+
+  fn remove(&mut self, oid: &ObjectId) -> Result<bool, Error> {
+    match fs::remove_file(self.object_path(oid)) {
+      Ok(_) => Ok(true),
+      Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+      Err(err) => io_result(Err(err)),
+    }
+  }
+
+  fn contains(&self, oid: &ObjectId) -> Result<bool, Error> {
+    Ok(self.object_path(oid).is_file())
+  }
+
+  fn oids(&self) -> Result<Vec<ObjectId>, Error> {
+    let mut ret: Vec<ObjectId> = Vec::new();
+    for dir in io_result(fs::read_dir(&self.path))? {
+      let dir = io_result(dir)?;
+      let prefix = dir.file_name().to_string_lossy().into_owned();
+      if prefix.len() != 2 || !dir.path().is_dir() {
+        continue;
+      }
+
+      for file in io_result(fs::read_dir(dir.path()))? {
+        let file = io_result(file)?;
+        let name = file.file_name().to_string_lossy().into_owned();
+        // Skips leftover temporary files and anything else that is not an object.
+        let hex = format!("{}{}", prefix, name);
+        if let Ok(oid) = ObjectId::from_hex(self.object_format, &hex) {
+          ret.push(oid);
+        }
+      }
+    }
+    ret.sort();
+
+    Ok(ret)
+  }
+
+  fn size(&self, oid: &ObjectId) -> Result<Option<u64>, Error> {
+    match fs::metadata(self.object_path(oid)) {
+      Ok(v) => Ok(Some(v.len())),
+      Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+      Err(err) => io_result(Err(err)),
+    }
+  }
+
+  fn stored_at(&self, oid: &ObjectId) -> Result<Option<SystemTime>, Error> {
+    match fs::metadata(self.object_path(oid)).and_then(|v| v.modified()) {
+      Ok(v) => Ok(Some(v)),
+      Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+      Err(err) => io_result(Err(err)),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_helper::{oid, temp_dir};
+
+  mod loose_object_storage {
+    use super::*;
+
+    #[test]
+    fn test_put_and_get() {
+      let dir = temp_dir("put-and-get");
+      let mut storage = LooseObjectStorage::new(&dir, ObjectFormat::Sha1).unwrap();
+      storage.put(oid(0xab), b"data").unwrap();
+
+      assert_eq!(storage.get(&oid(0xab)), Ok(Some(b"data".to_vec())));
+      assert_eq!(storage.get(&oid(1)), Ok(None));
+      assert_eq!(storage.contains(&oid(0xab)), Ok(true));
+      assert!(dir.join("ab").join("ab".repeat(19)).is_file());
+      assert_eq!(storage.size(&oid(0xab)), Ok(Some(4)));
+      assert_eq!(storage.size(&oid(1)), Ok(None));
+
+      let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_remove() {
+      let dir = temp_dir("remove");
+      let mut storage = LooseObjectStorage::new(&dir, ObjectFormat::Sha1).unwrap();
+      storage.put(oid(1), b"a").unwrap();
+
+      assert!(storage.stored_at(&oid(1)).unwrap().is_some());
+      assert_eq!(storage.remove(&oid(1)), Ok(true));
+      assert_eq!(storage.remove(&oid(1)), Ok(false));
+      assert_eq!(storage.get(&oid(1)), Ok(None));
+      assert_eq!(storage.stored_at(&oid(1)), Ok(None));
+
+      let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_oids() {
+      let dir = temp_dir("oids");
+      let mut storage = LooseObjectStorage::new(&dir, ObjectFormat::Sha1).unwrap();
+      storage.put(oid(2), b"b").unwrap();
+      storage.put(oid(1), b"a").unwrap();
+      fs::write(dir.join("01").join("tmp_garbage"), b"").unwrap();
+
+      let reopened = LooseObjectStorage::new(&dir, ObjectFormat::Sha1).unwrap();
+      assert_eq!(reopened.oids(), Ok(vec![oid(1), oid(2)]));
+
+      let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_oids_in_sha256_format() {
+      let dir = temp_dir("oids-sha256");
+      let mut storage = LooseObjectStorage::new(&dir, ObjectFormat::Sha256).unwrap();
+      let sha256 = ObjectFormat::Sha256.hash(b"a");
+      storage.put(sha256, b"a").unwrap();
+      // Ids of another format are never listed.
+      storage.put(oid(1), b"sha1").unwrap();
+
+      assert!(dir
+        .join(&sha256.to_string()[..2])
+        .join(&sha256.to_string()[2..])
+        .is_file());
+      assert_eq!(storage.get(&sha256), Ok(Some(b"a".to_vec())));
+      assert_eq!(storage.oids(), Ok(vec![sha256]));
+
+      let _ = fs::remove_dir_all(&dir);
+    }
+  }
+}
@@ -89,6 +89,10 @@ impl Namespace {
       namespace_type,
     }
   }
+
+  pub fn name(&self) -> &str {
+    &self.name
+  }
 }
 
 #[derive(PartialEq, Debug)]
@@ -96,6 +100,8 @@ pub enum NamespaceType {
   Domain,
   User,
   Repository,
+  // A git ref such as `refs/heads/master` inside a repository.
+  Ref,
 }
 
 impl ToString for NamespaceType {
@@ -104,6 +110,7 @@ impl ToString for NamespaceType {
       NamespaceType::Domain => "NamespaceType::Domain".to_owned(),
       NamespaceType::User => "NamespaceType::User".to_owned(),
       NamespaceType::Repository => "NamespaceType::Repository".to_owned(),
+      NamespaceType::Ref => "NamespaceType::Ref".to_owned(),
     }
   }
 }
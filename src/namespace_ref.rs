@@ -1,23 +1,35 @@
 use super::error::Error;
 use super::namespace::{Namespace, NamespaceType};
-use git2::Oid;
-use std::rc::Rc;
+use super::object_format::ObjectId;
+use std::sync::Arc;
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct NamespaceRef {
   namespace: Namespace,
-  oid: Oid,
-  pub parent: Option<Rc<NamespaceRef>>,
+  oid: ObjectId,
+  pub parent: Option<Arc<NamespaceRef>>,
 }
 
 impl NamespaceRef {
-  pub fn new(namespace: Namespace, oid: Oid, parent: Option<Rc<NamespaceRef>>) -> NamespaceRef {
+  pub fn new(
+    namespace: Namespace,
+    oid: ObjectId,
+    parent: Option<Arc<NamespaceRef>>,
+  ) -> NamespaceRef {
     NamespaceRef {
       namespace,
       oid,
       parent,
     }
   }
+
+  pub fn namespace(&self) -> &Namespace {
+    &self.namespace
+  }
+
+  pub fn oid(&self) -> ObjectId {
+    self.oid
+  }
 }
 
 // trait HasParent<T> {
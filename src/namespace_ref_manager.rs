@@ -1,19 +1,86 @@
 use super::error::Error;
-use super::namespace::Namespace;
+use super::namespace::{Namespace, NamespaceType};
 use super::namespace_ref::NamespaceRef;
-use std::rc::Rc;
+use super::object_format::ObjectId;
+use super::path_fragment::PathFragment;
+use std::collections::BTreeMap;
+use std::sync::Arc;
 
+// Refs of every fragment in a bundle, one `NamespaceRef` per git ref. A
+// fragment without refs is dropped.
+#[derive(Debug, PartialEq, Clone, Default)]
 pub struct NamespaceRefManager {
-  namepspace_refs: Vec<Rc<NamespaceRef>>,
+  namespace_refs: BTreeMap<PathFragment, BTreeMap<String, Arc<NamespaceRef>>>,
 }
 
 impl NamespaceRefManager {
   pub fn new() -> NamespaceRefManager {
     NamespaceRefManager {
-      namepspace_refs: Vec::new(),
+      namespace_refs: BTreeMap::new(),
     }
   }
 
+  pub fn fragments(&self) -> impl Iterator<Item = &PathFragment> {
+    self.namespace_refs.keys()
+  }
+
+  pub fn get(&self, fragment: &PathFragment, name: &str) -> Option<ObjectId> {
+    self
+      .namespace_refs
+      .get(fragment)
+      .and_then(|v| v.get(name))
+      .map(|v| v.oid())
+  }
+
+  pub fn refs(&self, fragment: &PathFragment) -> Option<BTreeMap<String, ObjectId>> {
+    self.namespace_refs.get(fragment).map(|refs| {
+      refs
+        .iter()
+        .map(|(name, v)| (name.clone(), v.oid()))
+        .collect()
+    })
+  }
+
+  // Every ref of every fragment as (fragment, name, target).
+  pub fn iter(&self) -> impl Iterator<Item = (&PathFragment, &str, ObjectId)> {
+    self.namespace_refs.iter().flat_map(|(fragment, refs)| {
+      refs
+        .iter()
+        .map(move |(name, v)| (fragment, name.as_str(), v.oid()))
+    })
+  }
+
+  pub fn insert(&mut self, fragment: &PathFragment, name: &str, oid: ObjectId) {
+    let namespace_ref = NamespaceRef::new(Namespace::new(NamespaceType::Ref, name), oid, None);
+    self
+      .namespace_refs
+      .entry(fragment.clone())
+      .or_default()
+      .insert(name.to_owned(), Arc::new(namespace_ref));
+  }
+
+  pub fn remove(&mut self, fragment: &PathFragment, name: &str) -> Option<ObjectId> {
+    let refs = self.namespace_refs.get_mut(fragment)?;
+    let ret = refs.remove(name).map(|v| v.oid());
+    if refs.is_empty() {
+      self.namespace_refs.remove(fragment);
+    }
+
+    ret
+  }
+
+  // Replaces every ref of `fragment`.
+  pub fn set_refs(&mut self, fragment: &PathFragment, refs: BTreeMap<String, ObjectId>) {
+    self.namespace_refs.remove(fragment);
+    for (name, oid) in refs {
+      self.insert(fragment, &name, oid);
+    }
+  }
+
+  pub fn remove_fragment(&mut self, fragment: &PathFragment) -> bool {
+    self.namespace_refs.remove(fragment).is_some()
+  }
+
   // pub fn add_namespace_ref(&mut self, ns_ref: Rc<&'a NamespaceRef<'a>>) -> Option<Error> {
   //   self.namepspace_refs.push(ns_ref);
 
@@ -82,6 +149,29 @@ mod tests {
 
   mod namespace_ref_manager {
     use super::*;
+    use crate::test_helper::oid;
+
+    #[test]
+    fn test_insert_and_remove() {
+      let mut manager = NamespaceRefManager::new();
+      let fragment = PathFragment::parse_full_qualified_fragment("github.com@a:repo").unwrap();
+      manager.insert(&fragment, "HEAD", oid(1));
+      manager.insert(&fragment, "refs/heads/master", oid(2));
+
+      assert_eq!(manager.get(&fragment, "HEAD"), Some(oid(1)));
+      assert_eq!(manager.refs(&fragment).map(|v| v.len()), Some(2));
+      assert_eq!(
+        manager
+          .iter()
+          .map(|(_, name, _)| name)
+          .collect::<Vec<&str>>(),
+        vec!["HEAD", "refs/heads/master"]
+      );
+
+      assert_eq!(manager.remove(&fragment, "HEAD"), Some(oid(1)));
+      assert_eq!(manager.remove(&fragment, "refs/heads/master"), Some(oid(2)));
+      assert_eq!(manager.fragments().count(), 0);
+    }
 
     mod add_namespace_ref {
       use super::*;
@@ -0,0 +1,193 @@
+use super::git_object::GitObject;
+use super::object_format::ObjectId;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::sync::Arc;
+
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct CacheStats {
+  pub hits: u64,
+  pub misses: u64,
+  pub objects: usize,
+  pub bytes: usize,
+}
+
+// A least-recently-used cache bounded by the total content size of the
+// objects it holds. Objects are shared with callers, so a hit costs no copy.
+// Keyed by oid, or by any other id such as a pack offset.
+#[derive(Debug)]
+pub struct ObjectCache<K = ObjectId> {
+  capacity: usize,
+  bytes: usize,
+  entries: HashMap<K, (Arc<GitObject>, u64)>,
+  // Last use tick to key; the first entry is the least recently used one.
+  order: BTreeMap<u64, K>,
+  tick: u64,
+  hits: u64,
+  misses: u64,
+}
+
+impl<K: Hash + Eq + Copy> ObjectCache<K> {
+  pub fn new(capacity: usize) -> ObjectCache<K> {
+    ObjectCache {
+      capacity,
+      bytes: 0,
+      entries: HashMap::new(),
+      order: BTreeMap::new(),
+      tick: 0,
+      hits: 0,
+      misses: 0,
+    }
+  }
+
+  pub fn capacity(&self) -> usize {
+    self.capacity
+  }
+
+  pub fn get(&mut self, oid: &K) -> Option<Arc<GitObject>> {
+    let tick = self.next_tick();
+    match self.entries.get_mut(oid) {
+      Some((object, used)) => {
+        self.order.remove(used);
+        self.order.insert(tick, *oid);
+        *used = tick;
+        self.hits += 1;
+
+        Some(Arc::clone(object))
+      }
+      None => {
+        self.misses += 1;
+
+        None
+      }
+    }
+  }
+
+  pub fn insert(&mut self, oid: K, object: Arc<GitObject>) {
+    let size = object.content().len();
+    // Objects that would evict everything else are not worth caching.
+    if size > self.capacity {
+      return;
+    }
+
+    self.remove(&oid);
+    while self.bytes + size > self.capacity {
+      let lru = match self.order.iter().next() {
+        Some((_, v)) => *v,
+        None => break,
+      };
+      self.remove(&lru);
+    }
+
+    let tick = self.next_tick();
+    self.order.insert(tick, oid);
+    self.entries.insert(oid, (object, tick));
+    self.bytes += size;
+  }
+
+  pub fn remove(&mut self, oid: &K) -> bool {
+    match self.entries.remove(oid) {
+      Some((object, used)) => {
+        self.order.remove(&used);
+        self.bytes -= object.content().len();
+
+        true
+      }
+      None => false,
+    }
+  }
+
+  pub fn clear(&mut self) {
+    self.entries.clear();
+    self.order.clear();
+    self.bytes = 0;
+  }
+
+  pub fn stats(&self) -> CacheStats {
+    CacheStats {
+      hits: self.hits,
+      misses: self.misses,
+      objects: self.entries.len(),
+      bytes: self.bytes,
+    }
+  }
+
+  fn next_tick(&mut self) -> u64 {
+    self.tick += 1;
+
+    self.tick
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::object_format::ObjectFormat;
+  use crate::test_helper::blob;
+
+  fn entry(content: &[u8]) -> (ObjectId, Arc<GitObject>) {
+    let object = blob(content);
+
+    (object.object_id(ObjectFormat::Sha1), Arc::new(object))
+  }
+
+  mod object_cache {
+    use super::*;
+
+    #[test]
+    fn test_get() {
+      let mut cache = ObjectCache::new(100);
+      let (oid, object) = entry(b"hello");
+      assert_eq!(cache.get(&oid), None);
+      cache.insert(oid, object.clone());
+
+      assert_eq!(cache.get(&oid), Some(object));
+      assert_eq!(
+        cache.stats(),
+        CacheStats {
+          hits: 1,
+          misses: 1,
+          objects: 1,
+          bytes: 5,
+        }
+      );
+    }
+
+    #[test]
+    fn test_insert_evicts_least_recently_used() {
+      let mut cache = ObjectCache::new(10);
+      let (a, object_a) = entry(b"aaaa");
+      let (b, object_b) = entry(b"bbbb");
+      let (c, object_c) = entry(b"cccc");
+      cache.insert(a, object_a);
+      cache.insert(b, object_b);
+      cache.get(&a);
+      cache.insert(c, object_c);
+
+      assert!(cache.get(&a).is_some());
+      assert!(cache.get(&b).is_none());
+      assert!(cache.get(&c).is_some());
+      assert_eq!(cache.stats().bytes, 8);
+    }
+
+    #[test]
+    fn test_insert_skips_objects_larger_than_capacity() {
+      let mut cache = ObjectCache::new(4);
+      let (oid, object) = entry(b"too large");
+      cache.insert(oid, object);
+
+      assert_eq!(cache.stats().objects, 0);
+    }
+
+    #[test]
+    fn test_remove() {
+      let mut cache = ObjectCache::new(100);
+      let (oid, object) = entry(b"hello");
+      cache.insert(oid, object);
+
+      assert!(cache.remove(&oid));
+      assert!(!cache.remove(&oid));
+      assert_eq!(cache.stats().bytes, 0);
+    }
+  }
+}
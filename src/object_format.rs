@@ -0,0 +1,229 @@
+use super::error::Error;
+use git2::Oid;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Copy)]
+pub enum ObjectFormat {
+  Sha1,
+  Sha256,
+}
+
+impl ObjectFormat {
+  pub fn from_name(name: &str) -> Result<ObjectFormat, Error> {
+    match name {
+      "sha1" => Ok(ObjectFormat::Sha1),
+      "sha256" => Ok(ObjectFormat::Sha256),
+      _ => Err(Error::UnsupportedObjectFormat(name.to_owned())),
+    }
+  }
+
+  pub fn name(self) -> &'static str {
+    match self {
+      ObjectFormat::Sha1 => "sha1",
+      ObjectFormat::Sha256 => "sha256",
+    }
+  }
+
+  pub fn id_length(self) -> usize {
+    match self {
+      ObjectFormat::Sha1 => 20,
+      ObjectFormat::Sha256 => 32,
+    }
+  }
+
+  pub fn hex_length(self) -> usize {
+    self.id_length() * 2
+  }
+
+  pub fn hash(self, data: &[u8]) -> ObjectId {
+    self.hash_chunks(&[data])
+  }
+
+  // Hashes the concatenation of `chunks` without copying them into one buffer.
+  pub fn hash_chunks(self, chunks: &[&[u8]]) -> ObjectId {
+    let bytes = match self {
+      ObjectFormat::Sha1 => digest::<Sha1>(chunks),
+      ObjectFormat::Sha256 => digest::<Sha256>(chunks),
+    };
+    let mut ret = ObjectId::zero(self);
+    ret.bytes[..bytes.len()].copy_from_slice(&bytes);
+
+    ret
+  }
+}
+
+impl fmt::Display for ObjectFormat {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.name())
+  }
+}
+
+// Large enough for the longest id of every format; ids of shorter formats
+// leave the tail zeroed so that ids stay `Copy` and cheap to compare.
+const MAX_ID_LENGTH: usize = 32;
+
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Copy)]
+pub struct ObjectId {
+  format: ObjectFormat,
+  bytes: [u8; MAX_ID_LENGTH],
+}
+
+impl ObjectId {
+  pub fn from_bytes(format: ObjectFormat, bytes: &[u8]) -> Result<ObjectId, Error> {
+    if bytes.len() != format.id_length() {
+      return Err(Error::InvalidObjectId(to_hex(bytes)));
+    }
+
+    let mut ret = ObjectId::zero(format);
+    ret.bytes[..bytes.len()].copy_from_slice(bytes);
+
+    Ok(ret)
+  }
+
+  pub fn from_hex(format: ObjectFormat, hex: &str) -> Result<ObjectId, Error> {
+    if hex.len() != format.hex_length() || !hex.bytes().all(|v| v.is_ascii_hexdigit()) {
+      return Err(Error::InvalidObjectId(hex.to_owned()));
+    }
+
+    let bytes: Vec<u8> = (0..hex.len())
+      .step_by(2)
+      .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap_or_default())
+      .collect();
+
+    ObjectId::from_bytes(format, &bytes)
+  }
+
+  pub fn zero(format: ObjectFormat) -> ObjectId {
+    ObjectId {
+      format,
+      bytes: [0; MAX_ID_LENGTH],
+    }
+  }
+
+  pub fn format(&self) -> ObjectFormat {
+    self.format
+  }
+
+  pub fn as_bytes(&self) -> &[u8] {
+    &self.bytes[..self.format.id_length()]
+  }
+
+  pub fn to_oid(&self) -> Result<Oid, Error> {
+    match self.format {
+      ObjectFormat::Sha1 => match Oid::from_bytes(self.as_bytes()) {
+        Ok(v) => Ok(v),
+        Err(_) => Err(Error::InvalidObjectId(self.to_string())),
+      },
+      format => Err(Error::UnsupportedObjectFormat(format.name().to_owned())),
+    }
+  }
+}
+
+impl From<Oid> for ObjectId {
+  fn from(oid: Oid) -> ObjectId {
+    let mut ret = ObjectId::zero(ObjectFormat::Sha1);
+    ret.bytes[..20].copy_from_slice(oid.as_bytes());
+
+    ret
+  }
+}
+
+impl fmt::Display for ObjectId {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", to_hex(self.as_bytes()))
+  }
+}
+
+fn digest<D: Digest>(chunks: &[&[u8]]) -> Vec<u8> {
+  let mut hasher = D::new();
+  for chunk in chunks {
+    hasher.input(chunk);
+  }
+
+  hasher.result().to_vec()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+  bytes.iter().map(|v| format!("{:02x}", v)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  mod object_format {
+    use super::*;
+
+    #[test]
+    fn test_from_name() {
+      assert_eq!(ObjectFormat::from_name("sha1"), Ok(ObjectFormat::Sha1));
+      assert_eq!(ObjectFormat::from_name("sha256"), Ok(ObjectFormat::Sha256));
+      assert_eq!(
+        ObjectFormat::from_name("md5"),
+        Err(Error::UnsupportedObjectFormat("md5".to_owned()))
+      );
+    }
+
+    #[test]
+    fn test_hash() {
+      let data = vec![
+        (
+          ObjectFormat::Sha1,
+          "2e65efe2a145dda7ee51d1741299f848e5bf752e",
+        ),
+        (
+          ObjectFormat::Sha256,
+          "eb337bcee2061c5313c9a1392116b6c76039e9e30d71467ae359b36277e17dc7",
+        ),
+      ];
+
+      for (format, expected) in data {
+        let actual = format.hash(b"blob 1\0a");
+
+        assert_eq!(actual.to_string(), expected);
+        assert_eq!(actual.as_bytes().len(), format.id_length());
+      }
+    }
+  }
+
+  mod object_id {
+    use super::*;
+
+    #[test]
+    fn test_from_hex() {
+      let hex = "eb337bcee2061c5313c9a1392116b6c76039e9e30d71467ae359b36277e17dc7";
+      let actual = ObjectId::from_hex(ObjectFormat::Sha256, hex).unwrap();
+
+      assert_eq!(actual.format(), ObjectFormat::Sha256);
+      assert_eq!(actual.to_string(), hex);
+    }
+
+    #[test]
+    fn test_from_hex_when_length_does_not_match_format() {
+      let hex = "2e65efe2a145dda7ee51d1741299f848e5bf752e";
+      let actual = ObjectId::from_hex(ObjectFormat::Sha256, hex);
+
+      assert_eq!(actual, Err(Error::InvalidObjectId(hex.to_owned())));
+    }
+
+    #[test]
+    fn test_to_oid() {
+      let oid = Oid::from_str("2e65efe2a145dda7ee51d1741299f848e5bf752e").unwrap();
+      let actual = ObjectId::from(oid).to_oid();
+
+      assert_eq!(actual, Ok(oid));
+    }
+
+    #[test]
+    fn test_to_oid_when_format_is_sha256() {
+      let actual = ObjectFormat::Sha256.hash(b"").to_oid();
+
+      assert_eq!(
+        actual,
+        Err(Error::UnsupportedObjectFormat("sha256".to_owned()))
+      );
+    }
+  }
+}
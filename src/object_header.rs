@@ -0,0 +1,123 @@
+use super::object_format::{ObjectFormat, ObjectId};
+
+pub type Header = (String, Vec<u8>);
+
+pub fn split_body(content: &[u8]) -> Option<(&[u8], &[u8])> {
+  let mut pos = 0;
+
+  while pos < content.len() {
+    if content[pos] == b'\n' {
+      return Some((&content[..pos], &content[pos + 1..]));
+    }
+
+    pos += content[pos..].iter().position(|&v| v == b'\n')? + 1;
+  }
+
+  None
+}
+
+pub fn parse_headers(section: &[u8]) -> Option<Vec<Header>> {
+  let mut ret: Vec<Header> = Vec::new();
+  if section.is_empty() {
+    return Some(ret);
+  }
+
+  let section = section.strip_suffix(b"\n")?;
+  for line in section.split(|&v| v == b'\n') {
+    match line.first() {
+      Some(b' ') => {
+        let (_, value) = ret.last_mut()?;
+        value.push(b'\n');
+        value.extend_from_slice(&line[1..]);
+      }
+      _ => {
+        let pos = line.iter().position(|&v| v == b' ')?;
+        let key = String::from_utf8(line[..pos].to_vec()).ok()?;
+        ret.push((key, line[pos + 1..].to_vec()));
+      }
+    }
+  }
+
+  Some(ret)
+}
+
+pub fn write_header(out: &mut Vec<u8>, key: &str, value: &[u8]) {
+  out.extend_from_slice(key.as_bytes());
+  out.push(b' ');
+  for (i, line) in value.split(|&v| v == b'\n').enumerate() {
+    if i != 0 {
+      out.extend_from_slice(b"\n ");
+    }
+    out.extend_from_slice(line);
+  }
+  out.push(b'\n');
+}
+
+pub fn parse_hex_oid(value: &[u8], format: ObjectFormat) -> Option<ObjectId> {
+  let value = std::str::from_utf8(value).ok()?;
+
+  ObjectId::from_hex(format, value).ok()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_split_body() {
+    let actual = split_body(b"tree abc\nparent def\n\nmessage\n");
+    let expected: (&[u8], &[u8]) = (b"tree abc\nparent def\n", b"message\n");
+
+    assert_eq!(actual, Some(expected));
+  }
+
+  #[test]
+  fn test_split_body_when_separator_is_missing() {
+    let actual = split_body(b"tree abc\nparent def\n");
+
+    assert_eq!(actual, None);
+  }
+
+  #[test]
+  fn test_parse_headers_with_continuation_lines() {
+    let actual = parse_headers(b"tree abc\ngpgsig line1\n line2\n \n line4\n");
+    let expected = vec![
+      ("tree".to_owned(), b"abc".to_vec()),
+      ("gpgsig".to_owned(), b"line1\nline2\n\nline4".to_vec()),
+    ];
+
+    assert_eq!(actual, Some(expected));
+  }
+
+  #[test]
+  fn test_write_header() {
+    let mut actual = Vec::new();
+    write_header(&mut actual, "gpgsig", b"line1\nline2\n\nline4");
+
+    assert_eq!(actual, b"gpgsig line1\n line2\n \n line4\n".to_vec());
+  }
+
+  #[test]
+  fn test_parse_hex_oid() {
+    let sha1: &[u8] = b"0123456789abcdef0123456789abcdef01234567";
+    let sha256: &[u8] = b"0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+    let data: Vec<(&[u8], ObjectFormat, bool)> = vec![
+      (sha1, ObjectFormat::Sha1, true),
+      (sha1, ObjectFormat::Sha256, false),
+      (sha256, ObjectFormat::Sha256, true),
+      (sha256, ObjectFormat::Sha1, false),
+      (b"0123456789abcdef", ObjectFormat::Sha1, false),
+      (
+        b"0123456789abcdef0123456789abcdef0123456z",
+        ObjectFormat::Sha1,
+        false,
+      ),
+    ];
+
+    for (value, format, expected) in data {
+      let actual = parse_hex_oid(value, format);
+
+      assert_eq!(actual.is_some(), expected);
+    }
+  }
+}
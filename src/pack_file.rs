@@ -0,0 +1,740 @@
+use super::delta;
+use super::error::Error;
+use super::git_object::GitObject;
+use super::object_cache::ObjectCache;
+use super::object_format::{ObjectFormat, ObjectId};
+use super::pack_index::PackIndex;
+use flate2::read::ZlibDecoder;
+use git2::ObjectType;
+use std::collections::HashMap;
+use std::io::prelude::*;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+const SIGNATURE: &[u8] = b"PACK";
+const HEADER_LENGTH: usize = 12;
+// The deepest chain `git pack-objects --depth` accepts.
+const MAX_DELTA_DEPTH: usize = 4095;
+// Bounds the resolved objects kept between lookups, like git's
+// `core.deltaBaseCacheLimit`, so that objects sharing a chain inflate it once.
+const DELTA_BASE_CACHE_LIMIT: usize = 32 * 1024 * 1024;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum PackEntryKind {
+  Base(ObjectType),
+  OfsDelta(u64),
+  RefDelta(ObjectId),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct PackEntry {
+  pub offset: u64,
+  pub kind: PackEntryKind,
+  pub size: u64,
+  pub data_offset: u64,
+}
+
+pub struct PackFile {
+  data: Vec<u8>,
+  version: u32,
+  object_count: u32,
+  object_format: ObjectFormat,
+  // Keyed by entry offset.
+  bases: Mutex<ObjectCache<u64>>,
+}
+
+impl PackFile {
+  pub fn new(data: Vec<u8>) -> Result<PackFile, Error> {
+    PackFile::with_format(data, ObjectFormat::Sha1)
+  }
+
+  // The format decides the trailing checksum and the ids of REF_DELTA bases.
+  pub fn with_format(data: Vec<u8>, object_format: ObjectFormat) -> Result<PackFile, Error> {
+    let checksum_length = object_format.id_length();
+    if data.len() < HEADER_LENGTH + checksum_length || !data.starts_with(SIGNATURE) {
+      return Err(malformed("invalid signature"));
+    }
+
+    let version = read_u32(&data[4..8]);
+    if version != 2 && version != 3 {
+      return Err(Error::UnsupportedPackVersion(version));
+    }
+    let object_count = read_u32(&data[8..12]);
+
+    let (body, checksum) = data.split_at(data.len() - checksum_length);
+    if object_format.hash(body).as_bytes() != checksum {
+      return Err(Error::PackChecksumMismatch);
+    }
+
+    Ok(PackFile {
+      data,
+      version,
+      object_count,
+      object_format,
+      bases: Mutex::new(ObjectCache::new(DELTA_BASE_CACHE_LIMIT)),
+    })
+  }
+
+  pub fn version(&self) -> u32 {
+    self.version
+  }
+
+  pub fn object_count(&self) -> u32 {
+    self.object_count
+  }
+
+  pub fn object_format(&self) -> ObjectFormat {
+    self.object_format
+  }
+
+  pub fn checksum(&self) -> &[u8] {
+    &self.data[self.data.len() - self.object_format.id_length()..]
+  }
+
+  pub fn entry_at(&self, offset: u64) -> Result<PackEntry, Error> {
+    let body = self.body();
+    let mut pos = offset as usize;
+
+    let mut byte = read_byte(body, &mut pos)?;
+    let type_number = (byte >> 4) & 0x07;
+    let mut size = u64::from(byte & 0x0f);
+    let mut shift = 4;
+    while byte & 0x80 != 0 {
+      byte = read_byte(body, &mut pos)?;
+      if shift > 57 {
+        return Err(malformed("entry size overflow"));
+      }
+      size |= u64::from(byte & 0x7f) << shift;
+      shift += 7;
+    }
+
+    let kind = match type_number {
+      1 => PackEntryKind::Base(ObjectType::Commit),
+      2 => PackEntryKind::Base(ObjectType::Tree),
+      3 => PackEntryKind::Base(ObjectType::Blob),
+      4 => PackEntryKind::Base(ObjectType::Tag),
+      6 => PackEntryKind::OfsDelta(read_base_offset(body, &mut pos, offset)?),
+      7 => {
+        let end = pos + self.object_format.id_length();
+        if end > body.len() {
+          return Err(malformed("truncated base oid"));
+        }
+        let oid = match ObjectId::from_bytes(self.object_format, &body[pos..end]) {
+          Ok(v) => Ok(v),
+          Err(_) => Err(malformed("invalid base oid")),
+        }?;
+        pos = end;
+        PackEntryKind::RefDelta(oid)
+      }
+      _ => return Err(malformed("invalid entry type")),
+    };
+
+    Ok(PackEntry {
+      offset,
+      kind,
+      size,
+      data_offset: pos as u64,
+    })
+  }
+
+  pub fn entries(&self) -> Result<Vec<PackEntry>, Error> {
+    let mut ret: Vec<PackEntry> = Vec::new();
+    let mut offset = HEADER_LENGTH as u64;
+
+    for _ in 0..self.object_count {
+      let entry = self.entry_at(offset)?;
+      let (_, consumed) = self.inflate(&entry)?;
+      offset = entry.data_offset + consumed as u64;
+      ret.push(entry);
+    }
+
+    if offset as usize != self.body().len() {
+      return Err(malformed("unexpected data after entries"));
+    }
+
+    Ok(ret)
+  }
+
+  pub fn object_at(
+    &self,
+    offset: u64,
+    find_base: &dyn Fn(&ObjectId) -> Result<Option<GitObject>, Error>,
+  ) -> Result<GitObject, Error> {
+    self.resolve(offset, None, find_base)
+  }
+
+  pub fn find_object(&self, index: &PackIndex, oid: &ObjectId) -> Result<Option<GitObject>, Error> {
+    if index.pack_checksum() != self.checksum() {
+      return Err(Error::PackIndexMismatch);
+    }
+
+    let entry = match index.find(oid) {
+      Some(v) => v,
+      None => return Ok(None),
+    };
+    // The index may be stale or the entry corrupt; never hand out an object
+    // under an id it does not hash to.
+    let object = self.resolve(entry.offset, Some(index), &|_| Ok(None))?;
+    let actual = object.object_id(self.object_format);
+    if actual != *oid {
+      return Err(Error::ObjectIdMismatch {
+        expected: oid.to_string(),
+        actual: actual.to_string(),
+      });
+    }
+
+    Ok(Some(object))
+  }
+
+  // Walks the delta chain down to its base, or to an entry resolved by an
+  // earlier lookup, without recursing. REF_DELTA bases found in `index` stay
+  // in this pack; the depth limit also ends chains that loop back on
+  // themselves.
+  fn resolve(
+    &self,
+    offset: u64,
+    index: Option<&PackIndex>,
+    find_base: &dyn Fn(&ObjectId) -> Result<Option<GitObject>, Error>,
+  ) -> Result<GitObject, Error> {
+    let mut deltas: Vec<(u64, Vec<u8>)> = Vec::new();
+    let mut offset = offset;
+    let mut ret = loop {
+      if deltas.len() > MAX_DELTA_DEPTH {
+        return Err(malformed("delta chain too deep"));
+      }
+      if let Some(v) = lock_bases(&self.bases).get(&offset) {
+        break v;
+      }
+
+      let entry = self.entry_at(offset)?;
+      let (data, _) = self.inflate(&entry)?;
+      match entry.kind {
+        PackEntryKind::Base(object_type) => {
+          let object = Arc::new(GitObject::new(object_type, data.len() as u64, &data));
+          lock_bases(&self.bases).insert(offset, Arc::clone(&object));
+          break object;
+        }
+        PackEntryKind::OfsDelta(base_offset) => {
+          deltas.push((offset, data));
+          offset = base_offset;
+        }
+        PackEntryKind::RefDelta(oid) => match index.and_then(|v| v.find(&oid)) {
+          Some(base) => {
+            deltas.push((offset, data));
+            offset = base.offset;
+          }
+          None => match find_base(&oid)? {
+            Some(base) => {
+              deltas.push((offset, data));
+              break Arc::new(base);
+            }
+            None => return Err(Error::MissingDeltaBase(oid.to_string())),
+          },
+        },
+      }
+    };
+
+    while let Some((offset, delta)) = deltas.pop() {
+      ret = Arc::new(apply_delta(&ret, &delta)?);
+      lock_bases(&self.bases).insert(offset, Arc::clone(&ret));
+    }
+
+    Ok(GitObject::clone(&ret))
+  }
+
+  pub fn objects(&self) -> Result<Vec<(ObjectId, GitObject)>, Error> {
+    self.objects_with(&|_| Ok(None))
+  }
+
+  pub fn objects_with(
+    &self,
+    find_base: &dyn Fn(&ObjectId) -> Result<Option<GitObject>, Error>,
+  ) -> Result<Vec<(ObjectId, GitObject)>, Error> {
+    let entries = self.entries()?;
+    let mut resolved: HashMap<u64, (ObjectId, GitObject)> = HashMap::new();
+    let mut offsets: HashMap<ObjectId, u64> = HashMap::new();
+    let mut pending: Vec<&PackEntry> = entries.iter().collect();
+
+    // Bases of REF_DELTA entries may appear anywhere in the pack, so keep
+    // resolving until no entry makes progress.
+    while !pending.is_empty() {
+      let mut unresolved: Vec<&PackEntry> = Vec::new();
+
+      for &entry in pending.iter() {
+        let base = match entry.kind {
+          PackEntryKind::Base(_) => None,
+          PackEntryKind::OfsDelta(base_offset) => match resolved.get(&base_offset) {
+            Some((_, object)) => Some(object.clone()),
+            None => {
+              unresolved.push(entry);
+              continue;
+            }
+          },
+          PackEntryKind::RefDelta(oid) => match offsets.get(&oid) {
+            Some(base_offset) => Some(resolved[base_offset].1.clone()),
+            None => match find_base(&oid)? {
+              Some(object) => Some(object),
+              None => {
+                unresolved.push(entry);
+                continue;
+              }
+            },
+          },
+        };
+
+        let (data, _) = self.inflate(entry)?;
+        let object = match (entry.kind, base) {
+          (PackEntryKind::Base(object_type), _) => {
+            GitObject::new(object_type, data.len() as u64, &data)
+          }
+          (_, Some(base)) => apply_delta(&base, &data)?,
+          (_, None) => unreachable!(),
+        };
+        let oid = object.object_id(self.object_format);
+        offsets.insert(oid, entry.offset);
+        resolved.insert(entry.offset, (oid, object));
+      }
+
+      if unresolved.len() == pending.len() {
+        let missing = match unresolved[0].kind {
+          PackEntryKind::RefDelta(oid) => oid.to_string(),
+          _ => unresolved[0].offset.to_string(),
+        };
+        return Err(Error::MissingDeltaBase(missing));
+      }
+      pending = unresolved;
+    }
+
+    let mut ret: Vec<(u64, (ObjectId, GitObject))> = resolved.into_iter().collect();
+    ret.sort_by_key(|(offset, _)| *offset);
+
+    Ok(ret.into_iter().map(|(_, v)| v).collect())
+  }
+
+  fn body(&self) -> &[u8] {
+    &self.data[..self.data.len() - self.object_format.id_length()]
+  }
+
+  fn inflate(&self, entry: &PackEntry) -> Result<(Vec<u8>, usize), Error> {
+    let input = &self.body()[entry.data_offset as usize..];
+    let mut decoder = ZlibDecoder::new(input);
+    let mut ret: Vec<u8> = Vec::new();
+
+    match decoder.read_to_end(&mut ret) {
+      Ok(_) => Ok(()),
+      Err(_) => Err(Error::InvalidZlibData),
+    }?;
+    if ret.len() as u64 != entry.size {
+      return Err(Error::LengthMismatch {
+        expected: entry.size,
+        actual: ret.len() as u64,
+      });
+    }
+
+    Ok((ret, decoder.total_in() as usize))
+  }
+}
+
+// The cache is only touched between whole operations that cannot panic
+// halfway, so a poisoned lock can still be used.
+fn lock_bases(bases: &Mutex<ObjectCache<u64>>) -> MutexGuard<'_, ObjectCache<u64>> {
+  match bases.lock() {
+    Ok(v) => v,
+    Err(err) => err.into_inner(),
+  }
+}
+
+fn apply_delta(base: &GitObject, delta: &[u8]) -> Result<GitObject, Error> {
+  let content = delta::apply(base.content(), delta)?;
+
+  Ok(GitObject::new(
+    base.object_type(),
+    content.len() as u64,
+    &content,
+  ))
+}
+
+fn read_base_offset(body: &[u8], pos: &mut usize, offset: u64) -> Result<u64, Error> {
+  let mut byte = read_byte(body, pos)?;
+  let mut distance = u64::from(byte & 0x7f);
+  while byte & 0x80 != 0 {
+    byte = read_byte(body, pos)?;
+    distance = match (distance + 1).checked_mul(128) {
+      Some(v) => v + u64::from(byte & 0x7f),
+      None => return Err(malformed("base offset overflow")),
+    };
+  }
+
+  match offset.checked_sub(distance) {
+    Some(v) if distance != 0 => Ok(v),
+    _ => Err(malformed("base offset out of range")),
+  }
+}
+
+fn read_byte(body: &[u8], pos: &mut usize) -> Result<u8, Error> {
+  match body.get(*pos) {
+    Some(v) => {
+      *pos += 1;
+      Ok(*v)
+    }
+    None => Err(malformed("unexpected end of pack")),
+  }
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+  let mut ret: u32 = 0;
+  for byte in bytes.iter().take(4) {
+    ret = (ret << 8) | u32::from(*byte);
+  }
+
+  ret
+}
+
+fn malformed(reason: &str) -> Error {
+  Error::MalformedPack(reason.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_helper::{git, oid, temp_dir};
+  use flate2::write::ZlibEncoder;
+  use flate2::Compression;
+  use std::fs;
+
+  fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data).unwrap();
+
+    encoder.finish().unwrap()
+  }
+
+  fn entry_header(type_number: u8, size: usize) -> Vec<u8> {
+    let mut ret = vec![(type_number << 4) | (size as u8 & 0x0f)];
+    let mut size = size >> 4;
+    while size != 0 {
+      let last = ret.len() - 1;
+      ret[last] |= 0x80;
+      ret.push(size as u8 & 0x7f);
+      size >>= 7;
+    }
+
+    ret
+  }
+
+  fn build_pack(entries: Vec<Vec<u8>>) -> Vec<u8> {
+    let mut ret = b"PACK\0\0\0\x02".to_vec();
+    ret.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+    for entry in entries {
+      ret.extend(entry);
+    }
+    let checksum = ObjectFormat::Sha1.hash(&ret).as_bytes().to_vec();
+    ret.extend(checksum);
+
+    ret
+  }
+
+  fn blob_entry(content: &[u8]) -> Vec<u8> {
+    let mut ret = entry_header(3, content.len());
+    ret.extend(deflate(content));
+
+    ret
+  }
+
+  // A version 2 index over `entries`, which must be sorted by id.
+  fn build_index(entries: &[(ObjectId, u64)], pack_checksum: &[u8]) -> PackIndex {
+    let mut ret = b"\xfftOc\0\0\0\x02".to_vec();
+    for i in 0..256 {
+      let count = entries
+        .iter()
+        .filter(|(v, _)| v.as_bytes()[0] <= i as u8)
+        .count();
+      ret.extend_from_slice(&(count as u32).to_be_bytes());
+    }
+    for (oid, _) in entries {
+      ret.extend_from_slice(oid.as_bytes());
+    }
+    ret.extend(vec![0; entries.len() * 4]);
+    for (_, offset) in entries {
+      ret.extend_from_slice(&(*offset as u32).to_be_bytes());
+    }
+    ret.extend_from_slice(pack_checksum);
+    let checksum = ObjectFormat::Sha1.hash(&ret);
+    ret.extend_from_slice(checksum.as_bytes());
+
+    PackIndex::parse(&ret).unwrap()
+  }
+
+  // base size 11, result size 12, copy 6 bytes from 0, insert "there!"
+  const DELTA: &[u8] = b"\x0b\x0c\x90\x06\x06there!";
+
+  fn fixture() -> Vec<u8> {
+    let base = blob_entry(b"hello world");
+    let base_length = base.len();
+
+    let mut ofs_delta = entry_header(6, DELTA.len());
+    ofs_delta.push(base_length as u8);
+    ofs_delta.extend(deflate(DELTA));
+
+    let base_oid = ObjectFormat::Sha1.hash(b"blob 11\0hello world");
+    let mut ref_delta = entry_header(7, DELTA.len());
+    ref_delta.extend_from_slice(base_oid.as_bytes());
+    ref_delta.extend(deflate(DELTA));
+
+    build_pack(vec![ref_delta, base, ofs_delta])
+  }
+
+  mod pack_file {
+    use super::*;
+
+    #[test]
+    fn test_new() {
+      let actual = PackFile::new(fixture()).unwrap();
+
+      assert_eq!(actual.version(), 2);
+      assert_eq!(actual.object_count(), 3);
+    }
+
+    #[test]
+    fn test_new_when_checksum_does_not_match() {
+      let mut data = fixture();
+      let last = data.len() - 1;
+      data[last] ^= 0xff;
+      let actual = PackFile::new(data);
+
+      assert_eq!(actual.err(), Some(Error::PackChecksumMismatch));
+    }
+
+    #[test]
+    fn test_new_when_signature_is_invalid() {
+      let actual = PackFile::new(vec![0; 32]);
+
+      assert_eq!(
+        actual.err(),
+        Some(Error::MalformedPack("invalid signature".to_owned()))
+      );
+    }
+
+    #[test]
+    fn test_entries() {
+      let pack = PackFile::new(fixture()).unwrap();
+      let actual = pack.entries().unwrap();
+      let base_oid = ObjectFormat::Sha1.hash(b"blob 11\0hello world");
+
+      assert_eq!(actual.len(), 3);
+      assert_eq!(actual[0].kind, PackEntryKind::RefDelta(base_oid));
+      assert_eq!(actual[1].kind, PackEntryKind::Base(ObjectType::Blob));
+      assert_eq!(actual[1].size, 11);
+      assert_eq!(actual[2].kind, PackEntryKind::OfsDelta(actual[1].offset));
+    }
+
+    #[test]
+    fn test_object_at() {
+      let pack = PackFile::new(fixture()).unwrap();
+      let entries = pack.entries().unwrap();
+      let actual = pack.object_at(entries[2].offset, &|_| Ok(None));
+      let expected = GitObject::new(ObjectType::Blob, 12, &b"hello there!".to_vec());
+
+      assert_eq!(actual, Ok(expected));
+    }
+
+    #[test]
+    fn test_object_at_when_ref_delta_base_is_missing() {
+      let pack = PackFile::new(fixture()).unwrap();
+      let base_oid = ObjectFormat::Sha1.hash(b"blob 11\0hello world");
+      let actual = pack.object_at(HEADER_LENGTH as u64, &|_| Ok(None));
+
+      assert_eq!(actual, Err(Error::MissingDeltaBase(base_oid.to_string())));
+    }
+
+    #[test]
+    fn test_find_object() {
+      let dir = temp_dir("find-object");
+      let pack_path = dir.join("fixture.pack");
+      let index_path = dir.join("fixture.idx");
+      fs::write(&pack_path, fixture()).unwrap();
+      git(
+        &dir,
+        &[
+          "index-pack",
+          "-o",
+          index_path.to_str().unwrap(),
+          pack_path.to_str().unwrap(),
+        ],
+      );
+
+      let pack = PackFile::new(fs::read(&pack_path).unwrap()).unwrap();
+      let index = PackIndex::parse(&fs::read(&index_path).unwrap()).unwrap();
+      let oid = ObjectFormat::Sha1.hash(b"blob 12\0hello there!");
+      let actual = pack.find_object(&index, &oid);
+      let expected = GitObject::new(ObjectType::Blob, 12, &b"hello there!".to_vec());
+
+      assert_eq!(actual, Ok(Some(expected)));
+      assert_eq!(
+        pack.find_object(&index, &ObjectId::zero(ObjectFormat::Sha1)),
+        Ok(None)
+      );
+
+      let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_object_at_when_delta_result_size_is_out_of_range() {
+      let base = blob_entry(b"hello world");
+      let base_length = base.len();
+      // base size 11, result size 2^56 - 1, insert "x"
+      let delta = b"\x0b\xff\xff\xff\xff\xff\xff\xff\x7f\x01x";
+      let mut ofs_delta = entry_header(6, delta.len());
+      ofs_delta.push(base_length as u8);
+      ofs_delta.extend(deflate(delta));
+      let pack = PackFile::new(build_pack(vec![base, ofs_delta])).unwrap();
+      let offset = (HEADER_LENGTH + base_length) as u64;
+      let actual = pack.object_at(offset, &|_| Ok(None));
+
+      assert_eq!(
+        actual,
+        Err(Error::MalformedDelta("result size out of range".to_owned()))
+      );
+    }
+
+    #[test]
+    fn test_find_object_when_index_names_other_object() {
+      let pack = PackFile::new(fixture()).unwrap();
+      let base_offset = pack.entries().unwrap()[1].offset;
+      let index = build_index(&[(oid(1), base_offset)], pack.checksum());
+      let actual = pack.find_object(&index, &oid(1));
+
+      assert_eq!(
+        actual,
+        Err(Error::ObjectIdMismatch {
+          expected: oid(1).to_string(),
+          actual: ObjectFormat::Sha1.hash(b"blob 11\0hello world").to_string(),
+        })
+      );
+    }
+
+    #[test]
+    fn test_find_object_reuses_resolved_bases() {
+      let pack = PackFile::new(fixture()).unwrap();
+      let entries = pack.entries().unwrap();
+      let result = ObjectFormat::Sha1.hash(b"blob 12\0hello there!");
+      let index = build_index(&[(result, entries[2].offset)], pack.checksum());
+      pack.find_object(&index, &result).unwrap();
+      pack.find_object(&index, &result).unwrap();
+      let actual = lock_bases(&pack.bases).stats();
+
+      // The second lookup stops at the resolved delta itself.
+      assert_eq!(actual.hits, 1);
+      assert_eq!(actual.objects, 2);
+    }
+
+    #[test]
+    fn test_find_object_when_ref_deltas_form_cycle() {
+      let (a, b) = (oid(1), oid(2));
+      let ref_delta = |base: &ObjectId| {
+        let mut ret = entry_header(7, DELTA.len());
+        ret.extend_from_slice(base.as_bytes());
+        ret.extend(deflate(DELTA));
+        ret
+      };
+      let first = ref_delta(&b);
+      let second_offset = (HEADER_LENGTH + first.len()) as u64;
+      let pack = PackFile::new(build_pack(vec![first, ref_delta(&a)])).unwrap();
+      let index = build_index(
+        &[(a, HEADER_LENGTH as u64), (b, second_offset)],
+        pack.checksum(),
+      );
+      let actual = pack.find_object(&index, &a);
+
+      assert_eq!(actual, Err(malformed("delta chain too deep")));
+    }
+
+    #[test]
+    fn test_objects() {
+      let pack = PackFile::new(fixture()).unwrap();
+      let actual = pack.objects().unwrap();
+      let contents: Vec<&[u8]> = actual.iter().map(|(_, v)| v.content().as_slice()).collect();
+
+      assert_eq!(
+        contents,
+        vec![
+          &b"hello there!"[..],
+          &b"hello world"[..],
+          &b"hello there!"[..]
+        ]
+      );
+      for (oid, object) in actual {
+        assert_eq!(object.object_id(ObjectFormat::Sha1), oid);
+      }
+    }
+
+    #[test]
+    fn test_objects_with_external_base() {
+      let delta = {
+        let mut ret = entry_header(7, DELTA.len());
+        let base_oid = ObjectFormat::Sha1.hash(b"blob 11\0hello world");
+        ret.extend_from_slice(base_oid.as_bytes());
+        ret.extend(deflate(DELTA));
+        ret
+      };
+      let pack = PackFile::new(build_pack(vec![delta])).unwrap();
+      let base = GitObject::new(ObjectType::Blob, 11, &b"hello world".to_vec());
+
+      assert!(pack.objects().is_err());
+
+      let actual = pack.objects_with(&|_| Ok(Some(base.clone()))).unwrap();
+
+      assert_eq!(actual[0].1.content(), &b"hello there!".to_vec());
+    }
+
+    #[test]
+    fn test_objects_from_git_pack_objects() {
+      for format in &[ObjectFormat::Sha1, ObjectFormat::Sha256] {
+        let dir = temp_dir(&format!("git-{}", format));
+        git(
+          &dir,
+          &["init", "-q", &format!("--object-format={}", format), "."],
+        );
+        let mut content = String::new();
+        for i in 0..200 {
+          content.push_str(&format!("line {}\n", i));
+        }
+        for i in 0..3 {
+          content.push_str(&format!("revision {}\n", i));
+          fs::write(dir.join("file.txt"), &content).unwrap();
+          git(&dir, &["add", "file.txt"]);
+          git(&dir, &["commit", "-q", "-m", "commit"]);
+        }
+        git(&dir, &["gc", "-q", "--aggressive"]);
+        let pack_path = fs::read_dir(dir.join(".git/objects/pack"))
+          .unwrap()
+          .map(|v| v.unwrap().path())
+          .find(|v| v.extension() == Some("pack".as_ref()))
+          .unwrap();
+
+        let pack = PackFile::with_format(fs::read(pack_path).unwrap(), *format).unwrap();
+        let mut actual: Vec<String> = pack
+          .objects()
+          .unwrap()
+          .iter()
+          .map(|(oid, _)| oid.to_string())
+          .collect();
+        actual.sort();
+        let listed = git(&dir, &["rev-list", "--objects", "--all"]);
+        let mut expected: Vec<String> = listed
+          .lines()
+          .map(|v| v.split(' ').next().unwrap().to_owned())
+          .collect();
+        expected.sort();
+
+        assert_eq!(actual, expected);
+        assert!(pack
+          .entries()
+          .unwrap()
+          .iter()
+          .any(|v| !matches!(v.kind, PackEntryKind::Base(_))));
+
+        let _ = fs::remove_dir_all(&dir);
+      }
+    }
+  }
+}
@@ -0,0 +1,440 @@
+use super::error::Error;
+use super::object_format::{ObjectFormat, ObjectId};
+
+const SIGNATURE: &[u8] = b"\xfftOc";
+const FANOUT_LENGTH: usize = 256 * 4;
+const LARGE_OFFSET_FLAG: u32 = 0x8000_0000;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct PackIndexEntry {
+  pub oid: ObjectId,
+  pub offset: u64,
+  pub crc32: Option<u32>,
+}
+
+#[derive(Debug)]
+pub struct PackIndex {
+  version: u32,
+  object_format: ObjectFormat,
+  entries: Vec<PackIndexEntry>,
+  fanout: Vec<u32>,
+  pack_checksum: Vec<u8>,
+}
+
+impl PackIndex {
+  pub fn parse(data: &[u8]) -> Result<PackIndex, Error> {
+    PackIndex::parse_with_format(data, ObjectFormat::Sha1)
+  }
+
+  // SHA-256 repositories keep the version 2 layout with longer ids and
+  // checksums.
+  pub fn parse_with_format(data: &[u8], format: ObjectFormat) -> Result<PackIndex, Error> {
+    let checksum_length = format.id_length();
+    if data.len() < checksum_length * 2 {
+      return Err(malformed("too short"));
+    }
+    let (body, checksum) = data.split_at(data.len() - checksum_length);
+    if format.hash(body).as_bytes() != checksum {
+      return Err(Error::PackIndexChecksumMismatch);
+    }
+
+    if data.starts_with(SIGNATURE) {
+      match read_u32(data, 4)? {
+        2 => parse_v2(data, format),
+        version => Err(Error::UnsupportedPackIndexVersion(version)),
+      }
+    } else if format == ObjectFormat::Sha1 {
+      parse_v1(data)
+    } else {
+      Err(Error::UnsupportedPackIndexVersion(1))
+    }
+  }
+
+  pub fn version(&self) -> u32 {
+    self.version
+  }
+
+  pub fn object_format(&self) -> ObjectFormat {
+    self.object_format
+  }
+
+  pub fn len(&self) -> usize {
+    self.entries.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.entries.is_empty()
+  }
+
+  pub fn entries(&self) -> &Vec<PackIndexEntry> {
+    &self.entries
+  }
+
+  pub fn pack_checksum(&self) -> &[u8] {
+    &self.pack_checksum
+  }
+
+  pub fn find(&self, oid: &ObjectId) -> Option<&PackIndexEntry> {
+    let candidates = self.fanout_range(oid.as_bytes()[0]);
+
+    match candidates.binary_search_by(|v| v.oid.cmp(oid)) {
+      Ok(pos) => Some(&candidates[pos]),
+      Err(_) => None,
+    }
+  }
+
+  pub fn find_by_prefix(&self, prefix: &str) -> Result<Option<&PackIndexEntry>, Error> {
+    let prefix = prefix.to_lowercase();
+    if prefix.is_empty()
+      || prefix.len() > self.object_format.hex_length()
+      || !prefix.bytes().all(|v| v.is_ascii_hexdigit())
+    {
+      return Err(Error::InvalidObjectId(prefix));
+    }
+
+    let candidates = match u8::from_str_radix(prefix.get(..2).unwrap_or(""), 16) {
+      Ok(first) => self.fanout_range(first),
+      Err(_) => &self.entries[..],
+    };
+    let start = candidates.partition_point(|v| v.oid.to_string() < prefix);
+    let mut matched = candidates[start..]
+      .iter()
+      .take_while(|v| v.oid.to_string().starts_with(&prefix));
+
+    match (matched.next(), matched.next()) {
+      (Some(entry), None) => Ok(Some(entry)),
+      (Some(_), Some(_)) => Err(Error::AmbiguousObjectId(prefix)),
+      _ => Ok(None),
+    }
+  }
+
+  fn fanout_range(&self, first_byte: u8) -> &[PackIndexEntry] {
+    let end = self.fanout[first_byte as usize] as usize;
+    let start = match first_byte {
+      0 => 0,
+      v => self.fanout[v as usize - 1] as usize,
+    };
+
+    &self.entries[start..end]
+  }
+}
+
+fn parse_v1(data: &[u8]) -> Result<PackIndex, Error> {
+  let format = ObjectFormat::Sha1;
+  let entry_length = 4 + format.id_length();
+  let fanout = parse_fanout(data, 0)?;
+  let count = fanout[255] as usize;
+  let entries_start = FANOUT_LENGTH;
+  check_length(data, entries_start, count, entry_length, format)?;
+
+  let mut entries: Vec<PackIndexEntry> = Vec::with_capacity(count);
+  for i in 0..count {
+    let pos = entries_start + i * entry_length;
+    let offset = u64::from(read_u32(data, pos)?);
+    let oid = read_oid(data, pos + 4, format)?;
+    entries.push(PackIndexEntry {
+      oid,
+      offset,
+      crc32: None,
+    });
+  }
+
+  let checksum_start = entries_start + count * entry_length;
+  build(1, format, entries, fanout, data, checksum_start)
+}
+
+fn parse_v2(data: &[u8], format: ObjectFormat) -> Result<PackIndex, Error> {
+  let oid_length = format.id_length();
+  let fanout = parse_fanout(data, 8)?;
+  let count = fanout[255] as usize;
+  let oids_start = 8 + FANOUT_LENGTH;
+  let crc_start = oids_start + count * oid_length;
+  let offsets_start = crc_start + count * 4;
+  let large_offsets_start = offsets_start + count * 4;
+  check_length(data, oids_start, count, oid_length + 8, format)?;
+
+  let mut entries: Vec<PackIndexEntry> = Vec::with_capacity(count);
+  let mut large_offset_count = 0;
+  for i in 0..count {
+    let oid = read_oid(data, oids_start + i * oid_length, format)?;
+    let crc32 = read_u32(data, crc_start + i * 4)?;
+    let offset = read_u32(data, offsets_start + i * 4)?;
+
+    let offset = if offset & LARGE_OFFSET_FLAG == 0 {
+      u64::from(offset)
+    } else {
+      let index = (offset & !LARGE_OFFSET_FLAG) as usize;
+      large_offset_count = large_offset_count.max(index + 1);
+      let pos = large_offsets_start + index * 8;
+      (u64::from(read_u32(data, pos)?) << 32) | u64::from(read_u32(data, pos + 4)?)
+    };
+
+    entries.push(PackIndexEntry {
+      oid,
+      offset,
+      crc32: Some(crc32),
+    });
+  }
+
+  let checksum_start = large_offsets_start + large_offset_count * 8;
+  build(2, format, entries, fanout, data, checksum_start)
+}
+
+fn build(
+  version: u32,
+  object_format: ObjectFormat,
+  entries: Vec<PackIndexEntry>,
+  fanout: Vec<u32>,
+  data: &[u8],
+  checksum_start: usize,
+) -> Result<PackIndex, Error> {
+  let checksum_length = object_format.id_length();
+  if checksum_start + checksum_length * 2 != data.len() {
+    return Err(malformed("unexpected length"));
+  }
+  if entries.windows(2).any(|v| v[0].oid > v[1].oid) {
+    return Err(malformed("object ids are not sorted"));
+  }
+
+  Ok(PackIndex {
+    version,
+    object_format,
+    entries,
+    fanout,
+    pack_checksum: data[checksum_start..checksum_start + checksum_length].to_vec(),
+  })
+}
+
+// Rejects an index too short for `count` entries before space for them is
+// reserved, since the count comes straight from the fanout table.
+fn check_length(
+  data: &[u8],
+  start: usize,
+  count: usize,
+  entry_length: usize,
+  format: ObjectFormat,
+) -> Result<(), Error> {
+  let expected = start as u64 + count as u64 * entry_length as u64 + format.id_length() as u64 * 2;
+  if (data.len() as u64) < expected {
+    return Err(malformed("truncated"));
+  }
+
+  Ok(())
+}
+
+fn parse_fanout(data: &[u8], start: usize) -> Result<Vec<u32>, Error> {
+  let mut ret: Vec<u32> = Vec::with_capacity(256);
+  for i in 0..256 {
+    ret.push(read_u32(data, start + i * 4)?);
+  }
+
+  if ret.windows(2).any(|v| v[0] > v[1]) {
+    return Err(malformed("fanout table is not monotonic"));
+  }
+
+  Ok(ret)
+}
+
+fn read_oid(data: &[u8], pos: usize, format: ObjectFormat) -> Result<ObjectId, Error> {
+  let bytes = data.get(pos..pos + format.id_length());
+  match bytes.map(|v| ObjectId::from_bytes(format, v)) {
+    Some(Ok(v)) => Ok(v),
+    _ => Err(malformed("truncated object id")),
+  }
+}
+
+fn read_u32(data: &[u8], pos: usize) -> Result<u32, Error> {
+  match data.get(pos..pos + 4) {
+    Some(v) => Ok(
+      (u32::from(v[0]) << 24) | (u32::from(v[1]) << 16) | (u32::from(v[2]) << 8) | u32::from(v[3]),
+    ),
+    None => Err(malformed("truncated")),
+  }
+}
+
+fn malformed(reason: &str) -> Error {
+  Error::MalformedPackIndex(reason.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_helper::{git, temp_dir};
+  use std::fs;
+  use std::path::{Path, PathBuf};
+
+  // Returns the pack path and `git verify-pack -v` entries as (oid, offset).
+  fn fixture(dir: &Path) -> (PathBuf, Vec<(ObjectId, u64)>) {
+    fixture_with_format(dir, ObjectFormat::Sha1)
+  }
+
+  fn fixture_with_format(dir: &Path, format: ObjectFormat) -> (PathBuf, Vec<(ObjectId, u64)>) {
+    git(
+      dir,
+      &["init", "-q", &format!("--object-format={}", format), "."],
+    );
+    for i in 0..20 {
+      fs::write(dir.join(format!("file{}.txt", i)), format!("content {}", i)).unwrap();
+      git(dir, &["add", "."]);
+      git(dir, &["commit", "-q", "-m", "commit"]);
+    }
+    git(dir, &["gc", "-q"]);
+    let pack = fs::read_dir(dir.join(".git/objects/pack"))
+      .unwrap()
+      .map(|v| v.unwrap().path())
+      .find(|v| v.extension() == Some("pack".as_ref()))
+      .unwrap();
+
+    let verified = git(dir, &["verify-pack", "-v", pack.to_str().unwrap()]);
+    let objects = verified
+      .lines()
+      .filter_map(|line| {
+        let columns: Vec<&str> = line.split_whitespace().collect();
+        let oid = columns.first().map(|v| ObjectId::from_hex(format, v));
+        match (oid, columns.get(4)) {
+          (Some(Ok(oid)), Some(offset)) => Some((oid, offset.parse().unwrap())),
+          _ => None,
+        }
+      })
+      .collect();
+
+    (pack, objects)
+  }
+
+  fn index_pack(dir: &Path, pack: &Path, version: &str) -> Vec<u8> {
+    let output = dir.join(format!("index-{}.idx", version));
+    git(
+      dir,
+      &[
+        "index-pack",
+        &format!("--index-version={}", version),
+        "-o",
+        output.to_str().unwrap(),
+        pack.to_str().unwrap(),
+      ],
+    );
+
+    fs::read(output).unwrap()
+  }
+
+  mod pack_index {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+      let dir = temp_dir("parse");
+      let (pack, objects) = fixture(&dir);
+      let data = vec![
+        (index_pack(&dir, &pack, "1"), 1),
+        (index_pack(&dir, &pack, "2"), 2),
+        (index_pack(&dir, &pack, "2,64"), 2),
+      ];
+
+      for (data, version) in data {
+        let actual = PackIndex::parse(&data).unwrap();
+
+        assert_eq!(actual.version(), version);
+        assert_eq!(actual.len(), objects.len());
+        for (oid, offset) in objects.iter() {
+          let entry = actual.find(oid).unwrap();
+
+          assert_eq!(entry.offset, *offset);
+          assert_eq!(entry.crc32.is_some(), version == 2);
+        }
+      }
+
+      let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_parse_with_sha256_format() {
+      let dir = temp_dir("parse-sha256");
+      let (pack, objects) = fixture_with_format(&dir, ObjectFormat::Sha256);
+      let data = fs::read(pack.with_extension("idx")).unwrap();
+      let actual = PackIndex::parse_with_format(&data, ObjectFormat::Sha256).unwrap();
+
+      assert_eq!(actual.version(), 2);
+      assert_eq!(actual.len(), objects.len());
+      assert_eq!(actual.pack_checksum().len(), 32);
+      for (oid, offset) in objects.iter() {
+        assert_eq!(actual.find(oid).map(|v| v.offset), Some(*offset));
+      }
+      assert_eq!(
+        PackIndex::parse(&data).err(),
+        Some(Error::PackIndexChecksumMismatch)
+      );
+
+      let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_parse_when_checksum_does_not_match() {
+      let dir = temp_dir("checksum");
+      let (pack, _) = fixture(&dir);
+      let mut data = index_pack(&dir, &pack, "2");
+      data[8] ^= 0xff;
+      let actual = PackIndex::parse(&data);
+
+      assert_eq!(actual.err(), Some(Error::PackIndexChecksumMismatch));
+
+      let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_parse_when_fanout_exceeds_length() {
+      for header in &[&b""[..], &b"\xfftOc\0\0\0\x02"[..]] {
+        let mut data = header.to_vec();
+        data.extend(vec![0xff; FANOUT_LENGTH]);
+        data.extend(vec![0; 20]);
+        let checksum = ObjectFormat::Sha1.hash(&data);
+        data.extend_from_slice(checksum.as_bytes());
+        let actual = PackIndex::parse(&data);
+
+        assert_eq!(actual.err(), Some(malformed("truncated")));
+      }
+    }
+
+    #[test]
+    fn test_find_by_prefix() {
+      let dir = temp_dir("prefix");
+      let (pack, objects) = fixture(&dir);
+      let index = PackIndex::parse(&index_pack(&dir, &pack, "2")).unwrap();
+      let (oid, _) = objects[0];
+      let hex = oid.to_string();
+
+      assert_eq!(
+        index.find_by_prefix(&hex[..12]).unwrap().map(|v| v.oid),
+        Some(oid)
+      );
+      assert_eq!(
+        index
+          .find_by_prefix(&hex.to_uppercase())
+          .unwrap()
+          .map(|v| v.oid),
+        Some(oid)
+      );
+      let shared = objects
+        .iter()
+        .map(|(v, _)| v.to_string()[..1].to_owned())
+        .find(|v| {
+          objects
+            .iter()
+            .filter(|(oid, _)| oid.to_string().starts_with(v.as_str()))
+            .count()
+            > 1
+        })
+        .unwrap();
+      assert_eq!(
+        index.find_by_prefix(&shared).err(),
+        Some(Error::AmbiguousObjectId(shared.clone()))
+      );
+      assert_eq!(
+        index.find_by_prefix("zz").err(),
+        Some(Error::InvalidObjectId("zz".to_owned()))
+      );
+      assert!(index.find(&ObjectId::zero(ObjectFormat::Sha1)).is_none());
+
+      let _ = fs::remove_dir_all(&dir);
+    }
+  }
+}
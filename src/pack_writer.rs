@@ -0,0 +1,411 @@
+use super::delta;
+use super::error::Error;
+use super::git_object::GitObject;
+use super::object_format::{ObjectFormat, ObjectId};
+use crc32fast::Hasher;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use git2::ObjectType;
+use std::collections::HashSet;
+use std::io::prelude::*;
+
+const PACK_VERSION: u32 = 2;
+const INDEX_SIGNATURE: &[u8] = b"\xfftOc";
+const INDEX_VERSION: u32 = 2;
+const LARGE_OFFSET_FLAG: u32 = 0x8000_0000;
+const DELTA_WINDOW: usize = 10;
+const MAX_DELTA_DEPTH: usize = 50;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct WrittenPack {
+  pub pack: Vec<u8>,
+  pub index: Vec<u8>,
+}
+
+pub struct PackWriter {
+  objects: Vec<(ObjectId, GitObject)>,
+  oids: HashSet<ObjectId>,
+  deltas: bool,
+  object_format: ObjectFormat,
+}
+
+struct WrittenEntry {
+  oid: ObjectId,
+  offset: u64,
+  crc32: u32,
+}
+
+impl PackWriter {
+  pub fn new(deltas: bool) -> PackWriter {
+    PackWriter::with_object_format(deltas, ObjectFormat::Sha1)
+  }
+
+  pub fn with_object_format(deltas: bool, object_format: ObjectFormat) -> PackWriter {
+    PackWriter {
+      objects: Vec::new(),
+      oids: HashSet::new(),
+      deltas,
+      object_format,
+    }
+  }
+
+  pub fn add(&mut self, object: GitObject) -> Result<ObjectId, Error> {
+    let oid = object.object_id(self.object_format);
+    if self.oids.insert(oid) {
+      self.objects.push((oid, object));
+    }
+
+    Ok(oid)
+  }
+
+  pub fn len(&self) -> usize {
+    self.objects.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.objects.is_empty()
+  }
+
+  pub fn write(&self) -> Result<WrittenPack, Error> {
+    let objects = self.ordered_objects();
+    let mut pack: Vec<u8> = Vec::new();
+    pack.extend_from_slice(b"PACK");
+    pack.extend_from_slice(&PACK_VERSION.to_be_bytes());
+    pack.extend_from_slice(&(objects.len() as u32).to_be_bytes());
+
+    let mut written: Vec<WrittenEntry> = Vec::with_capacity(objects.len());
+    let mut depths: Vec<usize> = Vec::with_capacity(objects.len());
+    for (i, (oid, object)) in objects.iter().enumerate() {
+      let offset = pack.len() as u64;
+      let (entry, depth) = match self.find_delta(&objects, &depths, i) {
+        Some((base, data)) => {
+          let mut entry = entry_header(6, data.len() as u64);
+          entry.extend(encode_base_offset(offset - written[base].offset));
+          entry.extend(deflate(&data)?);
+          (entry, depths[base] + 1)
+        }
+        None => {
+          // The header declares the size of what is deflated, whatever
+          // length the object claims.
+          let length = object.content().len() as u64;
+          let mut entry = entry_header(type_number(object.object_type())?, length);
+          entry.extend(deflate(object.content())?);
+          (entry, 0)
+        }
+      };
+
+      let mut hasher = Hasher::new();
+      hasher.update(&entry);
+      written.push(WrittenEntry {
+        oid: *oid,
+        offset,
+        crc32: hasher.finalize(),
+      });
+      depths.push(depth);
+      pack.extend(entry);
+    }
+
+    let checksum = self.object_format.hash(&pack).as_bytes().to_vec();
+    pack.extend_from_slice(&checksum);
+    let index = build_index(written, &checksum, self.object_format);
+
+    Ok(WrittenPack { pack, index })
+  }
+
+  // Like git, group objects by type and put larger objects first so that
+  // deltas are mostly computed against a bigger, earlier base.
+  fn ordered_objects(&self) -> Vec<&(ObjectId, GitObject)> {
+    let mut ret: Vec<&(ObjectId, GitObject)> = self.objects.iter().collect();
+    ret.sort_by(|(_, a), (_, b)| {
+      let a_type = type_number(a.object_type()).unwrap_or(0);
+      let b_type = type_number(b.object_type()).unwrap_or(0);
+      a_type
+        .cmp(&b_type)
+        .then(b.content().len().cmp(&a.content().len()))
+    });
+
+    ret
+  }
+
+  fn find_delta(
+    &self,
+    objects: &[&(ObjectId, GitObject)],
+    depths: &[usize],
+    index: usize,
+  ) -> Option<(usize, Vec<u8>)> {
+    if !self.deltas {
+      return None;
+    }
+
+    let (_, target) = objects[index];
+    let mut ret: Option<(usize, Vec<u8>)> = None;
+    for base in index.saturating_sub(DELTA_WINDOW)..index {
+      let (_, candidate) = objects[base];
+      if candidate.object_type() != target.object_type() || depths[base] >= MAX_DELTA_DEPTH {
+        continue;
+      }
+
+      let data = delta::compute(candidate.content(), target.content());
+      let limit = match &ret {
+        Some((_, v)) => v.len(),
+        None => target.content().len() / 2,
+      };
+      if data.len() < limit {
+        ret = Some((base, data));
+      }
+    }
+
+    ret
+  }
+}
+
+fn build_index(
+  mut entries: Vec<WrittenEntry>,
+  pack_checksum: &[u8],
+  object_format: ObjectFormat,
+) -> Vec<u8> {
+  entries.sort_by_key(|v| v.oid);
+
+  let mut ret: Vec<u8> = Vec::new();
+  ret.extend_from_slice(INDEX_SIGNATURE);
+  ret.extend_from_slice(&INDEX_VERSION.to_be_bytes());
+
+  let mut fanout = [0u32; 256];
+  for entry in entries.iter() {
+    fanout[entry.oid.as_bytes()[0] as usize] += 1;
+  }
+  let mut total = 0;
+  for count in fanout.iter() {
+    total += count;
+    ret.extend_from_slice(&total.to_be_bytes());
+  }
+
+  for entry in entries.iter() {
+    ret.extend_from_slice(entry.oid.as_bytes());
+  }
+  for entry in entries.iter() {
+    ret.extend_from_slice(&entry.crc32.to_be_bytes());
+  }
+
+  let mut large_offsets: Vec<u64> = Vec::new();
+  for entry in entries.iter() {
+    let offset = if entry.offset < u64::from(LARGE_OFFSET_FLAG) {
+      entry.offset as u32
+    } else {
+      large_offsets.push(entry.offset);
+      LARGE_OFFSET_FLAG | (large_offsets.len() - 1) as u32
+    };
+    ret.extend_from_slice(&offset.to_be_bytes());
+  }
+  for offset in large_offsets {
+    ret.extend_from_slice(&offset.to_be_bytes());
+  }
+
+  ret.extend_from_slice(pack_checksum);
+  let checksum = object_format.hash(&ret).as_bytes().to_vec();
+  ret.extend(checksum);
+
+  ret
+}
+
+fn entry_header(type_number: u8, size: u64) -> Vec<u8> {
+  let mut ret = vec![(type_number << 4) | (size as u8 & 0x0f)];
+  let mut size = size >> 4;
+  while size > 0 {
+    let last = ret.len() - 1;
+    ret[last] |= 0x80;
+    ret.push(size as u8 & 0x7f);
+    size >>= 7;
+  }
+
+  ret
+}
+
+// The inverse of the pack reader's base offset decoding: big-endian groups of
+// 7 bits where every continuation adds one to the remaining value.
+fn encode_base_offset(offset: u64) -> Vec<u8> {
+  let mut ret = vec![offset as u8 & 0x7f];
+  let mut offset = offset >> 7;
+  while offset > 0 {
+    offset -= 1;
+    ret.insert(0, 0x80 | (offset as u8 & 0x7f));
+    offset >>= 7;
+  }
+
+  ret
+}
+
+fn type_number(object_type: ObjectType) -> Result<u8, Error> {
+  match object_type {
+    ObjectType::Commit => Ok(1),
+    ObjectType::Tree => Ok(2),
+    ObjectType::Blob => Ok(3),
+    ObjectType::Tag => Ok(4),
+    _ => Err(Error::InvalidTypeName),
+  }
+}
+
+fn deflate(data: &[u8]) -> Result<Vec<u8>, Error> {
+  let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+  match encoder.write_all(data).and_then(|_| encoder.finish()) {
+    Ok(v) => Ok(v),
+    Err(_) => Err(Error::DeflateFailed),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::pack_file::{PackEntryKind, PackFile};
+  use crate::pack_index::PackIndex;
+  use crate::test_helper::{blob, git, temp_dir};
+  use std::fs;
+  use std::path::Path;
+
+  fn objects() -> Vec<GitObject> {
+    let base: Vec<u8> = (0..2000)
+      .flat_map(|v| format!("line {}\n", v).into_bytes())
+      .collect();
+    let mut changed = base.clone();
+    changed.extend_from_slice(b"one more line\n");
+
+    vec![
+      blob(&base),
+      blob(&changed),
+      blob(b"small"),
+      GitObject::new(ObjectType::Tree, 0, &Vec::new()),
+    ]
+  }
+
+  fn verify_pack(dir: &Path, written: &WrittenPack) -> String {
+    fs::write(dir.join("test.pack"), &written.pack).unwrap();
+    fs::write(dir.join("test.idx"), &written.index).unwrap();
+
+    git(dir, &["verify-pack", "-v", "test.idx"])
+  }
+
+  mod pack_writer {
+    use super::*;
+
+    #[test]
+    fn test_write() {
+      let mut writer = PackWriter::new(false);
+      for object in objects() {
+        writer.add(object).unwrap();
+      }
+      let written = writer.write().unwrap();
+      let pack = PackFile::new(written.pack.clone()).unwrap();
+      let index = PackIndex::parse(&written.index).unwrap();
+
+      assert_eq!(pack.object_count(), 4);
+      assert_eq!(index.len(), 4);
+      assert_eq!(index.pack_checksum(), pack.checksum());
+      for object in objects() {
+        let oid = object.object_id(ObjectFormat::Sha1);
+        assert_eq!(pack.find_object(&index, &oid), Ok(Some(object)));
+      }
+
+      let dir = temp_dir("write");
+      verify_pack(&dir, &written);
+      let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_write_with_deltas() {
+      let mut writer = PackWriter::new(true);
+      for object in objects() {
+        writer.add(object).unwrap();
+      }
+      let written = writer.write().unwrap();
+      let pack = PackFile::new(written.pack.clone()).unwrap();
+      let index = PackIndex::parse(&written.index).unwrap();
+      let deltas = pack
+        .entries()
+        .unwrap()
+        .into_iter()
+        .filter(|v| matches!(v.kind, PackEntryKind::OfsDelta(_)))
+        .count();
+
+      assert_eq!(deltas, 1);
+      for object in objects() {
+        let oid = object.object_id(ObjectFormat::Sha1);
+        assert_eq!(pack.find_object(&index, &oid), Ok(Some(object)));
+      }
+
+      let dir = temp_dir("write-with-deltas");
+      let output = verify_pack(&dir, &written);
+      assert!(output.contains("chain length = 1: 1 object"));
+      let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_write_with_sha256_format() {
+      let mut writer = PackWriter::with_object_format(true, ObjectFormat::Sha256);
+      for object in objects() {
+        writer.add(object).unwrap();
+      }
+      let written = writer.write().unwrap();
+      let pack = PackFile::with_format(written.pack.clone(), ObjectFormat::Sha256).unwrap();
+      let index = PackIndex::parse_with_format(&written.index, ObjectFormat::Sha256).unwrap();
+
+      assert_eq!(pack.checksum().len(), 32);
+      for object in objects() {
+        let oid = object.object_id(ObjectFormat::Sha256);
+        assert_eq!(pack.find_object(&index, &oid), Ok(Some(object)));
+      }
+
+      let dir = temp_dir("write-sha256");
+      git(
+        &dir,
+        &["init", "-q", "--bare", "--object-format=sha256", "."],
+      );
+      verify_pack(&dir, &written);
+      let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_write_when_length_differs_from_content() {
+      let object = GitObject::new(ObjectType::Blob, 99, &b"abc".to_vec());
+      let mut writer = PackWriter::new(false);
+      let oid = writer.add(object).unwrap();
+      let written = writer.write().unwrap();
+      let pack = PackFile::new(written.pack).unwrap();
+      let index = PackIndex::parse(&written.index).unwrap();
+
+      assert_eq!(pack.entries().unwrap()[0].size, 3);
+      assert_eq!(pack.find_object(&index, &oid), Ok(Some(blob(b"abc"))));
+    }
+
+    #[test]
+    fn test_add_ignores_duplicates() {
+      let mut writer = PackWriter::new(false);
+      writer.add(blob(b"a")).unwrap();
+      writer.add(blob(b"a")).unwrap();
+
+      assert_eq!(writer.len(), 1);
+    }
+
+    #[test]
+    fn test_write_empty() {
+      let written = PackWriter::new(true).write().unwrap();
+      let pack = PackFile::new(written.pack).unwrap();
+      let index = PackIndex::parse(&written.index).unwrap();
+
+      assert_eq!(pack.object_count(), 0);
+      assert!(index.is_empty());
+    }
+  }
+
+  #[test]
+  fn test_encode_base_offset() {
+    assert_eq!(encode_base_offset(0x7f), vec![0x7f]);
+    assert_eq!(encode_base_offset(0x80), vec![0x80, 0x00]);
+    assert_eq!(encode_base_offset(624_485), vec![0xa5, 0x8d, 0x65]);
+  }
+
+  #[test]
+  fn test_entry_header() {
+    assert_eq!(entry_header(3, 11), vec![0x3b]);
+    assert_eq!(entry_header(3, 624_485), vec![0xb5, 0xf6, 0xb0, 0x02]);
+  }
+}
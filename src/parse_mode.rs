@@ -0,0 +1,5 @@
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ParseMode {
+  Strict,
+  Lenient,
+}
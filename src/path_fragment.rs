@@ -1,6 +1,6 @@
 use super::error::Error;
 
-#[derive(PartialEq, Debug, Clone)]
+#[derive(PartialEq, Eq, Hash, PartialOrd, Ord, Debug, Clone)]
 pub struct PathFragment {
   pub domain: String,
   pub user: String,
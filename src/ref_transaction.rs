@@ -0,0 +1,184 @@
+use super::error::Error;
+use super::object_format::ObjectId;
+use super::path_fragment::PathFragment;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct RefUpdate {
+  pub fragment: PathFragment,
+  pub name: String,
+  // None means the ref must not exist yet.
+  pub expected: Option<ObjectId>,
+  // None deletes the ref.
+  pub new: Option<ObjectId>,
+}
+
+impl RefUpdate {
+  pub fn reference(&self) -> String {
+    format!("{} {}", self.fragment.to_string(), self.name)
+  }
+}
+
+// Ref updates staged across any number of fragments. They are applied by
+// `BundledRepository::commit_transaction`, either all of them or none.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct RefTransaction {
+  updates: Vec<RefUpdate>,
+}
+
+impl RefTransaction {
+  pub fn new() -> RefTransaction {
+    RefTransaction {
+      updates: Vec::new(),
+    }
+  }
+
+  pub fn update(
+    &mut self,
+    fragment: &PathFragment,
+    name: &str,
+    expected: Option<ObjectId>,
+    new: ObjectId,
+  ) -> Result<&mut RefTransaction, Error> {
+    self.push(fragment, name, expected, Some(new))
+  }
+
+  pub fn delete(
+    &mut self,
+    fragment: &PathFragment,
+    name: &str,
+    expected: ObjectId,
+  ) -> Result<&mut RefTransaction, Error> {
+    self.push(fragment, name, Some(expected), None)
+  }
+
+  pub fn updates(&self) -> &Vec<RefUpdate> {
+    &self.updates
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.updates.is_empty()
+  }
+
+  fn push(
+    &mut self,
+    fragment: &PathFragment,
+    name: &str,
+    expected: Option<ObjectId>,
+    new: Option<ObjectId>,
+  ) -> Result<&mut RefTransaction, Error> {
+    check_ref_name(name)?;
+    self.updates.push(RefUpdate {
+      fragment: fragment.clone(),
+      name: name.to_owned(),
+      expected,
+      new,
+    });
+
+    Ok(self)
+  }
+}
+
+// Follows `git check-ref-format --allow-onelevel`, so `HEAD` passes. Names
+// are stored one per line in the refs file and must stay within it.
+pub fn check_ref_name(name: &str) -> Result<(), Error> {
+  let invalid = name.is_empty()
+    || name == "@"
+    || name.ends_with('/')
+    || name.ends_with('.')
+    || name.contains("..")
+    || name.contains("@{")
+    || name
+      .chars()
+      .any(|v| v.is_ascii_control() || " ~^:?*[\\".contains(v))
+    || name
+      .split('/')
+      .any(|v| v.is_empty() || v.starts_with('.') || v.ends_with(".lock"));
+  if invalid {
+    return Err(Error::InvalidRefName(name.to_owned()));
+  }
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::object_format::ObjectFormat;
+
+  #[test]
+  fn test_update_and_delete() {
+    let fragment = PathFragment::parse_full_qualified_fragment("github.com@a:repo").unwrap();
+    let oid = ObjectId::from_bytes(ObjectFormat::Sha1, &[1; 20]).unwrap();
+    let mut transaction = RefTransaction::new();
+    transaction
+      .update(&fragment, "refs/heads/master", None, oid)
+      .unwrap()
+      .delete(&fragment, "refs/heads/old", oid)
+      .unwrap();
+
+    assert_eq!(transaction.updates().len(), 2);
+    assert_eq!(transaction.updates()[0].new, Some(oid));
+    assert_eq!(transaction.updates()[1].new, None);
+    assert_eq!(
+      transaction.updates()[1].reference(),
+      "github.com@a:repo refs/heads/old"
+    );
+  }
+
+  #[test]
+  fn test_update_when_name_is_invalid() {
+    let fragment = PathFragment::parse_full_qualified_fragment("github.com@a:repo").unwrap();
+    let oid = ObjectId::from_bytes(ObjectFormat::Sha1, &[1; 20]).unwrap();
+    let mut transaction = RefTransaction::new();
+    let actual = transaction.update(&fragment, "refs/heads/a\nb", None, oid);
+
+    assert_eq!(
+      actual.err(),
+      Some(Error::InvalidRefName("refs/heads/a\nb".to_owned()))
+    );
+    assert!(transaction.is_empty());
+    assert!(transaction.delete(&fragment, "", oid).is_err());
+  }
+
+  #[test]
+  fn test_check_ref_name() {
+    for name in &[
+      "HEAD",
+      "refs/heads/master",
+      "refs/tags/v1.0",
+      "refs/heads/a-b_c",
+    ] {
+      assert_eq!(check_ref_name(name), Ok(()), "{}", name);
+    }
+    for name in &[
+      "",
+      "@",
+      "refs/heads/a b",
+      "refs/heads/a\nb",
+      "refs/heads/a\tb",
+      "refs/heads/a\x7fb",
+      "refs/heads/a..b",
+      "refs/heads/a@{1}",
+      "refs/heads/a~1",
+      "refs/heads/a^",
+      "refs/heads/a:b",
+      "refs/heads/a?",
+      "refs/heads/a*",
+      "refs/heads/a[b",
+      "refs/heads/a\\b",
+      "refs/heads/",
+      "/refs/heads/a",
+      "refs//heads",
+      "refs/heads/.hidden",
+      "refs/heads/a.lock",
+      "refs/heads/a.",
+    ] {
+      assert_eq!(
+        check_ref_name(name),
+        Err(Error::InvalidRefName((*name).to_owned())),
+        "{:?}",
+        name
+      );
+    }
+  }
+}
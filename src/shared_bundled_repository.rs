@@ -0,0 +1,99 @@
+use super::bundled_repository::BundledRepository;
+use super::error::Error;
+use super::git_object::GitObject;
+use super::object_format::ObjectId;
+use std::fmt;
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+// A cloneable handle to one bundle. Readers run concurrently while writers
+// are serialized by the lock.
+#[derive(Clone)]
+pub struct SharedBundledRepository {
+  inner: Arc<RwLock<BundledRepository>>,
+}
+
+impl SharedBundledRepository {
+  pub fn new(repository: BundledRepository) -> SharedBundledRepository {
+    SharedBundledRepository {
+      inner: Arc::new(RwLock::new(repository)),
+    }
+  }
+
+  pub fn read(&self) -> Result<RwLockReadGuard<'_, BundledRepository>, Error> {
+    match self.inner.read() {
+      Ok(v) => Ok(v),
+      Err(_) => Err(Error::LockPoisoned),
+    }
+  }
+
+  pub fn write(&self) -> Result<RwLockWriteGuard<'_, BundledRepository>, Error> {
+    match self.inner.write() {
+      Ok(v) => Ok(v),
+      Err(_) => Err(Error::LockPoisoned),
+    }
+  }
+
+  pub fn find(&self, oid: &ObjectId) -> Result<Option<Arc<GitObject>>, Error> {
+    self.read()?.find(oid)
+  }
+
+  pub fn exists(&self, oid: &ObjectId) -> Result<bool, Error> {
+    self.read()?.exists(oid)
+  }
+
+  pub fn insert(&self, object: &GitObject) -> Result<ObjectId, Error> {
+    self.write()?.insert(object)
+  }
+}
+
+impl fmt::Debug for SharedBundledRepository {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "SharedBundledRepository")
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::namespace_ref::NamespaceRef;
+  use crate::namespace_ref_manager::NamespaceRefManager;
+  use crate::test_helper::blob;
+  use std::thread;
+
+  fn assert_send_sync<T: Send + Sync>() {}
+
+  #[test]
+  fn test_types_are_send_and_sync() {
+    assert_send_sync::<SharedBundledRepository>();
+    assert_send_sync::<BundledRepository>();
+    assert_send_sync::<NamespaceRef>();
+    assert_send_sync::<NamespaceRefManager>();
+  }
+
+  #[test]
+  fn test_concurrent_access() {
+    let mut repository = BundledRepository::new().unwrap();
+    repository.enable_cache(1024);
+    let shared = SharedBundledRepository::new(repository);
+    let oid = shared.insert(&blob(b"shared")).unwrap();
+
+    let handles: Vec<thread::JoinHandle<()>> = (0..8)
+      .map(|i| {
+        let shared = shared.clone();
+        thread::spawn(move || {
+          assert_eq!(shared.find(&oid), Ok(Some(Arc::new(blob(b"shared")))));
+          shared
+            .insert(&blob(format!("thread {}", i).as_bytes()))
+            .unwrap();
+        })
+      })
+      .collect();
+    for handle in handles {
+      handle.join().unwrap();
+    }
+
+    assert_eq!(shared.read().unwrap().len(), Ok(9));
+    let stats = shared.read().unwrap().cache_stats().unwrap();
+    assert_eq!(stats.hits + stats.misses, 8);
+  }
+}
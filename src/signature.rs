@@ -0,0 +1,168 @@
+use super::error::Error;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Signature {
+  pub name: Vec<u8>,
+  pub email: Vec<u8>,
+  pub time: i64,
+  pub offset: i32,
+  pub sign: char,
+}
+
+impl Signature {
+  pub fn new(name: &[u8], email: &[u8], time: i64, offset: i32) -> Signature {
+    Signature {
+      name: name.to_vec(),
+      email: email.to_vec(),
+      time,
+      offset,
+      sign: if offset < 0 { '-' } else { '+' },
+    }
+  }
+
+  pub fn parse(value: &[u8]) -> Result<Signature, Error> {
+    let malformed = || Error::MalformedSignature(String::from_utf8_lossy(value).into_owned());
+
+    let email_start = match value.iter().position(|&v| v == b'<') {
+      Some(v) => Ok(v),
+      None => Err(malformed()),
+    }?;
+    let email_end = match value[email_start..].iter().position(|&v| v == b'>') {
+      Some(v) => Ok(email_start + v),
+      None => Err(malformed()),
+    }?;
+
+    let name = match value[..email_start].split_last() {
+      Some((b' ', name)) => name,
+      _ => &value[..email_start],
+    };
+    let email = &value[email_start + 1..email_end];
+
+    let rest = match String::from_utf8(value[email_end + 1..].to_vec()) {
+      Ok(v) => Ok(v),
+      Err(_) => Err(malformed()),
+    }?;
+    let mut rest = rest.split(' ');
+    let (time, timezone) = match (rest.next(), rest.next(), rest.next(), rest.next()) {
+      (Some(""), Some(time), Some(timezone), None) => Ok((time, timezone)),
+      _ => Err(malformed()),
+    }?;
+    let time: i64 = match time.parse() {
+      Ok(v) => Ok(v),
+      Err(_) => Err(malformed()),
+    }?;
+    let (sign, offset) = match parse_timezone(timezone) {
+      Some(v) => Ok(v),
+      None => Err(malformed()),
+    }?;
+
+    Ok(Signature {
+      name: name.to_vec(),
+      email: email.to_vec(),
+      time,
+      offset,
+      sign,
+    })
+  }
+
+  pub fn to_bytes(&self) -> Vec<u8> {
+    let offset = self.offset.abs();
+    let mut ret = self.name.clone();
+    ret.extend_from_slice(b" <");
+    ret.extend_from_slice(&self.email);
+    ret.extend_from_slice(
+      format!(
+        "> {} {}{:02}{:02}",
+        self.time,
+        self.sign,
+        offset / 60,
+        offset % 60
+      )
+      .as_bytes(),
+    );
+
+    ret
+  }
+}
+
+// The parsed bytes are kept unless the signature has been changed since.
+pub(crate) fn signature_bytes(signature: &Signature, raw: &Option<Vec<u8>>) -> Vec<u8> {
+  match raw {
+    Some(v) if Signature::parse(v).as_ref() == Ok(signature) => v.clone(),
+    _ => signature.to_bytes(),
+  }
+}
+
+fn parse_timezone(timezone: &str) -> Option<(char, i32)> {
+  // Checked first so that the byte slicing below stays on char boundaries.
+  if !timezone.is_ascii() || timezone.len() != 5 {
+    return None;
+  }
+  if !timezone[1..].bytes().all(|v| v.is_ascii_digit()) {
+    return None;
+  }
+
+  let sign = match timezone.chars().next() {
+    Some(v) if v == '+' || v == '-' => Some(v),
+    _ => None,
+  }?;
+  let hours: i32 = timezone[1..3].parse().ok()?;
+  let minutes: i32 = timezone[3..5].parse().ok()?;
+  let offset = hours * 60 + minutes;
+
+  match sign {
+    '-' => Some((sign, -offset)),
+    _ => Some((sign, offset)),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  mod signature {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+      let actual = Signature::parse(b"tett23 <tett23@example.com> 1549870000 +0900");
+      let expected = Signature::new(b"tett23", b"tett23@example.com", 1549870000, 540);
+
+      assert_eq!(actual, Ok(expected));
+    }
+
+    #[test]
+    fn test_parse_negative_offset() {
+      let actual = Signature::parse(b"A U Thor <author@example.com> 0 -0130").unwrap();
+
+      assert_eq!(actual.name, b"A U Thor".to_vec());
+      assert_eq!(actual.offset, -90);
+      assert_eq!(actual.sign, '-');
+    }
+
+    #[test]
+    fn test_parse_when_value_is_malformed() {
+      let data: Vec<&[u8]> = vec![
+        b"tett23 tett23@example.com 0 +0000",
+        b"tett23 <tett23@example.com>",
+        b"tett23 <tett23@example.com> abc +0000",
+        b"tett23 <tett23@example.com> 0 0900",
+        "tett23 <tett23@example.com> 0 \u{e9}123".as_bytes(),
+      ];
+
+      for datum in data {
+        let actual = Signature::parse(datum);
+
+        assert!(actual.is_err());
+      }
+    }
+
+    #[test]
+    fn test_to_bytes_keeps_negative_zero_offset() {
+      let value = b"tett23 <tett23@example.com> 1549870000 -0000";
+      let actual = Signature::parse(value).unwrap().to_bytes();
+
+      assert_eq!(actual, value.to_vec());
+    }
+  }
+}
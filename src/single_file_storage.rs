@@ -0,0 +1,633 @@
+use super::error::Error;
+use super::object_format::{ObjectFormat, ObjectId};
+use super::storage::{io_result, Storage};
+use crc32fast::Hasher;
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::io::prelude::*;
+use std::io::{BufReader, BufWriter, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const SIGNATURE: &[u8] = b"CKSF";
+const VERSION: u32 = 1;
+// The signature, the version and the object format.
+const FILE_HEADER_LENGTH: u64 = 4 + 4 + 4;
+// Follows the oid in every record header: the time, the length and a checksum
+// of the oid and the length.
+const RECORD_FIELDS_LENGTH: usize = 8 + 8 + 4;
+const TOMBSTONE_LENGTH: u64 = u64::MAX;
+
+#[derive(Debug, Clone, Copy)]
+struct Record {
+  offset: u64,
+  length: usize,
+  stored_at: SystemTime,
+}
+
+// A file header followed by an append-only log of
+// `<oid><u64 unix time><u64 length><u32 crc32><data>` records. A length of u64::MAX
+// marks a removed oid and carries no data. The position of every live record
+// is indexed in memory when the file is opened, and `compact` drops the space
+// taken by removed ones.
+#[derive(Debug)]
+pub struct SingleFileStorage {
+  path: PathBuf,
+  object_format: ObjectFormat,
+  index: BTreeMap<ObjectId, Record>,
+  length: u64,
+}
+
+impl SingleFileStorage {
+  // A record cut short by a crash mid-append is dropped from the end of the
+  // file so that later appends start on a record boundary.
+  pub fn new(path: &Path, object_format: ObjectFormat) -> Result<SingleFileStorage, Error> {
+    let mut ret = SingleFileStorage {
+      path: path.to_path_buf(),
+      object_format,
+      index: BTreeMap::new(),
+      length: 0,
+    };
+    let file = match File::open(path) {
+      Ok(v) => v,
+      Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(ret),
+      Err(err) => return io_result(Err(err)),
+    };
+    let file_length = io_result(file.metadata())?.len();
+
+    let mut reader = BufReader::new(file);
+    if file_length < FILE_HEADER_LENGTH {
+      // The file header is written together with the first record, so a crash
+      // during the first write may leave only part of it.
+      let mut header = Vec::new();
+      io_result(reader.read_to_end(&mut header))?;
+      if !file_header(object_format).starts_with(&header) {
+        return Err(Error::StorageFailed {
+          detail: "truncated file header".to_owned(),
+        });
+      }
+      truncate(path, 0)?;
+
+      return Ok(ret);
+    }
+    let mut header = [0u8; FILE_HEADER_LENGTH as usize];
+    io_result(reader.read_exact(&mut header))?;
+    check_file_header(&header, object_format)?;
+    let mut pos = FILE_HEADER_LENGTH;
+
+    let oid_length = object_format.id_length();
+    let header_length = (oid_length + RECORD_FIELDS_LENGTH) as u64;
+    let mut header = vec![0u8; header_length as usize];
+    while file_length - pos >= header_length {
+      io_result(reader.read_exact(&mut header))?;
+      let oid = ObjectId::from_bytes(object_format, &header[..oid_length])?;
+      let mut time = [0u8; 8];
+      time.copy_from_slice(&header[oid_length..oid_length + 8]);
+      let mut length = [0u8; 8];
+      length.copy_from_slice(&header[oid_length + 8..oid_length + 16]);
+      let start = pos + header_length;
+
+      // The time is left out of the checksum because `refresh` rewrites it
+      // in place.
+      let length = u64::from_be_bytes(length);
+      if header[oid_length + 16..] != record_checksum(&oid, length)[..] {
+        return Err(Error::StorageFailed {
+          detail: format!("corrupt record at offset {}", pos),
+        });
+      }
+      if length == TOMBSTONE_LENGTH {
+        ret.index.remove(&oid);
+        pos = start;
+        continue;
+      }
+      // Records are only ever appended, so an intact header whose data runs
+      // past the end of the file belongs to the torn last write.
+      if length > file_length - start {
+        break;
+      }
+
+      io_result(reader.seek(SeekFrom::Current(length as i64)))?;
+      ret.index.entry(oid).or_insert(Record {
+        offset: start,
+        length: length as usize,
+        stored_at: UNIX_EPOCH + Duration::from_secs(u64::from_be_bytes(time)),
+      });
+      pos = start + length;
+    }
+
+    // Only the torn last record is dropped: what is left is either part of a
+    // record header or a record whose data is shorter than its length.
+    if pos < file_length {
+      truncate(path, pos)?;
+    }
+    ret.length = pos;
+
+    Ok(ret)
+  }
+
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  // Returns the offset the data was written at.
+  fn append(
+    &mut self,
+    oid: &ObjectId,
+    length: u64,
+    data: &[u8],
+    time: SystemTime,
+  ) -> Result<u64, Error> {
+    let mut record: Vec<u8> = Vec::new();
+    // The file header is written together with the first record.
+    if self.length == 0 {
+      record.extend(file_header(self.object_format));
+    }
+    write_record(&mut record, oid, length, time);
+    record.extend_from_slice(data);
+
+    let mut file = io_result(
+      OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(false)
+        .open(&self.path),
+    )?;
+    // Writes at the known end rather than appending, so that a partial record
+    // left by a failed write is overwritten by the next one.
+    let result = file
+      .seek(SeekFrom::Start(self.length))
+      .and_then(|_| file.write_all(&record));
+    if let Err(err) = result {
+      let _ = file.set_len(self.length);
+      return io_result(Err(err));
+    }
+    self.length += record.len() as u64;
+
+    Ok(self.length - data.len() as u64)
+  }
+
+  // Rewrites the time in place; the rest of the record is left untouched.
+  fn refresh(&mut self, oid: &ObjectId, time: SystemTime) -> Result<(), Error> {
+    let record = match self.index.get_mut(oid) {
+      Some(v) => v,
+      None => return Ok(()),
+    };
+    let time_offset = record.offset - RECORD_FIELDS_LENGTH as u64;
+    io_result(
+      OpenOptions::new()
+        .write(true)
+        .open(&self.path)
+        .and_then(|mut file| {
+          file.seek(SeekFrom::Start(time_offset))?;
+          file.write_all(&unix_seconds(time).to_be_bytes())
+        }),
+    )?;
+    record.stored_at = time;
+
+    Ok(())
+  }
+
+  // Rewrites the file with only the live records. The new file replaces the
+  // old one by a rename, so a crash leaves one of the two intact.
+  pub fn compact(&mut self) -> Result<(), Error> {
+    let mut file_name = match self.path.file_name() {
+      Some(v) => v.to_os_string(),
+      None => {
+        return Err(Error::StorageFailed {
+          detail: format!("invalid path: {}", self.path.display()),
+        })
+      }
+    };
+    file_name.push(".compacting");
+    let temp = self.path.with_file_name(file_name);
+
+    if self.length == 0 {
+      return Ok(());
+    }
+
+    let mut reader = io_result(File::open(&self.path))?;
+    let mut writer = BufWriter::new(io_result(File::create(&temp))?);
+    let mut out = file_header(self.object_format);
+    let mut index: BTreeMap<ObjectId, Record> = BTreeMap::new();
+    let mut length = 0;
+    for (oid, record) in self.index.iter() {
+      write_record(&mut out, oid, record.length as u64, record.stored_at);
+      let start = out.len();
+      out.resize(start + record.length, 0);
+      io_result(
+        reader
+          .seek(SeekFrom::Start(record.offset))
+          .and_then(|_| reader.read_exact(&mut out[start..])),
+      )?;
+      io_result(writer.write_all(&out))?;
+
+      length += out.len() as u64;
+      index.insert(
+        *oid,
+        Record {
+          offset: length - record.length as u64,
+          ..*record
+        },
+      );
+      out.clear();
+    }
+    if index.is_empty() {
+      io_result(writer.write_all(&out))?;
+      length = out.len() as u64;
+    }
+    io_result(
+      writer
+        .into_inner()
+        .map_err(|err| err.into_error())
+        .and_then(|file| file.sync_all()),
+    )?;
+    io_result(fs::rename(&temp, &self.path))?;
+
+    self.index = index;
+    self.length = length;
+
+    Ok(())
+  }
+}
+
+impl Storage for SingleFileStorage {
+  fn get(&self, oid: &ObjectId) -> Result<Option<Vec<u8>>, Error> {
+    let record = match self.index.get(oid) {
+      Some(v) => *v,
+      None => return Ok(None),
+    };
+
+    let mut ret = vec![0u8; record.length];
+    io_result(File::open(&self.path).and_then(|mut file| {
+      file.seek(SeekFrom::Start(record.offset))?;
+      file.read_exact(&mut ret)
+    }))?;
+
+    Ok(Some(ret))
+  }
+
+  fn put(&mut self, oid: ObjectId, data: &[u8]) -> Result<(), Error> {
+    if self.index.contains_key(&oid) {
+      return self.refresh(&oid, SystemTime::now());
+    }
+
+    let length = data.len() as u64;
+    if length == TOMBSTONE_LENGTH {
+      return Err(Error::StorageFailed {
+        detail: format!("object too large: {}", oid),
+      });
+    }
+
+    let stored_at = SystemTime::now();
+    let offset = self.append(&oid, length, data, stored_at)?;
+    let record = Record {
+      offset,
+      length: data.len(),
+      stored_at,
+    };
+    self.index.insert(oid, record);
+
+    Ok(())
+  }
+
+  fn remove(&mut self, oid: &ObjectId) -> Result<bool, Error> {
+    if !self.index.contains_key(oid) {
+      return Ok(false);
+    }
+
+    self.append(oid, TOMBSTONE_LENGTH, &[], SystemTime::now())?;
+    self.index.remove(oid);
+
+    Ok(true)
+  }
+
+  fn contains(&self, oid: &ObjectId) -> Result<bool, Error> {
+    Ok(self.index.contains_key(oid))
+  }
+
+  fn oids(&self) -> Result<Vec<ObjectId>, Error> {
+    Ok(self.index.keys().cloned().collect())
+  }
+
+  fn size(&self, oid: &ObjectId) -> Result<Option<u64>, Error> {
+    Ok(self.index.get(oid).map(|v| v.length as u64))
+  }
+
+  fn stored_at(&self, oid: &ObjectId) -> Result<Option<SystemTime>, Error> {
+    Ok(self.index.get(oid).map(|v| v.stored_at))
+  }
+
+  fn compact(&mut self) -> Result<(), Error> {
+    SingleFileStorage::compact(self)
+  }
+}
+
+// Uses the hash version numbers of git's own binary formats.
+fn format_id(format: ObjectFormat) -> u32 {
+  match format {
+    ObjectFormat::Sha1 => 1,
+    ObjectFormat::Sha256 => 2,
+  }
+}
+
+fn truncate(path: &Path, length: u64) -> Result<(), Error> {
+  io_result(
+    OpenOptions::new()
+      .write(true)
+      .open(path)
+      .and_then(|file| file.set_len(length)),
+  )
+}
+
+fn file_header(format: ObjectFormat) -> Vec<u8> {
+  let mut ret = SIGNATURE.to_vec();
+  ret.extend_from_slice(&VERSION.to_be_bytes());
+  ret.extend_from_slice(&format_id(format).to_be_bytes());
+
+  ret
+}
+
+fn check_file_header(header: &[u8], format: ObjectFormat) -> Result<(), Error> {
+  if &header[..4] != SIGNATURE {
+    return Err(Error::StorageFailed {
+      detail: "invalid signature".to_owned(),
+    });
+  }
+  let mut version = [0u8; 4];
+  version.copy_from_slice(&header[4..8]);
+  let version = u32::from_be_bytes(version);
+  if version != VERSION {
+    return Err(Error::StorageFailed {
+      detail: format!("unsupported version: {}", version),
+    });
+  }
+
+  let mut actual = [0u8; 4];
+  actual.copy_from_slice(&header[8..12]);
+  let actual = u32::from_be_bytes(actual);
+  if actual != format_id(format) {
+    return Err(Error::ObjectFormatMismatch {
+      expected: format.to_string(),
+      actual: match actual {
+        1 => ObjectFormat::Sha1.to_string(),
+        2 => ObjectFormat::Sha256.to_string(),
+        v => v.to_string(),
+      },
+    });
+  }
+
+  Ok(())
+}
+
+fn write_record(out: &mut Vec<u8>, oid: &ObjectId, length: u64, time: SystemTime) {
+  out.extend_from_slice(oid.as_bytes());
+  out.extend_from_slice(&unix_seconds(time).to_be_bytes());
+  out.extend_from_slice(&length.to_be_bytes());
+  out.extend_from_slice(&record_checksum(oid, length));
+}
+
+fn record_checksum(oid: &ObjectId, length: u64) -> [u8; 4] {
+  let mut hasher = Hasher::new();
+  hasher.update(oid.as_bytes());
+  hasher.update(&length.to_be_bytes());
+
+  hasher.finalize().to_be_bytes()
+}
+
+fn unix_seconds(time: SystemTime) -> u64 {
+  match time.duration_since(UNIX_EPOCH) {
+    Ok(v) => v.as_secs(),
+    Err(_) => 0,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_helper::{oid, temp_dir};
+  use std::fs;
+
+  mod single_file_storage {
+    use super::*;
+
+    #[test]
+    fn test_put_and_get() {
+      let path = temp_dir("put-and-get").join("objects.bin");
+      let mut storage = SingleFileStorage::new(&path, ObjectFormat::Sha1).unwrap();
+      storage.put(oid(1), b"first").unwrap();
+      storage.put(oid(2), b"second").unwrap();
+      storage.put(oid(1), b"ignored").unwrap();
+
+      assert_eq!(storage.get(&oid(1)), Ok(Some(b"first".to_vec())));
+      assert_eq!(storage.get(&oid(2)), Ok(Some(b"second".to_vec())));
+      assert_eq!(storage.get(&oid(3)), Ok(None));
+      assert_eq!(storage.contains(&oid(2)), Ok(true));
+      assert_eq!(storage.size(&oid(2)), Ok(Some(6)));
+      assert_eq!(storage.size(&oid(3)), Ok(None));
+
+      let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_new_reads_existing_file() {
+      let path = temp_dir("reopen").join("objects.bin");
+      let mut storage = SingleFileStorage::new(&path, ObjectFormat::Sha1).unwrap();
+      storage.put(oid(2), b"b").unwrap();
+      storage.put(oid(1), b"a").unwrap();
+
+      let reopened = SingleFileStorage::new(&path, ObjectFormat::Sha1).unwrap();
+      assert_eq!(reopened.oids(), Ok(vec![oid(1), oid(2)]));
+      assert_eq!(reopened.get(&oid(2)), Ok(Some(b"b".to_vec())));
+
+      let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_remove() {
+      let path = temp_dir("remove").join("objects.bin");
+      let mut storage = SingleFileStorage::new(&path, ObjectFormat::Sha1).unwrap();
+      storage.put(oid(1), b"a").unwrap();
+      storage.put(oid(2), b"b").unwrap();
+
+      assert_eq!(storage.remove(&oid(1)), Ok(true));
+      assert_eq!(storage.remove(&oid(1)), Ok(false));
+      assert_eq!(storage.get(&oid(1)), Ok(None));
+
+      let reopened = SingleFileStorage::new(&path, ObjectFormat::Sha1).unwrap();
+      assert_eq!(reopened.oids(), Ok(vec![oid(2)]));
+      assert!(reopened.stored_at(&oid(2)).unwrap().is_some());
+      assert_eq!(reopened.stored_at(&oid(1)), Ok(None));
+
+      let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_new_when_record_is_truncated() {
+      let path = temp_dir("truncated").join("objects.bin");
+      let mut storage = SingleFileStorage::new(&path, ObjectFormat::Sha1).unwrap();
+      storage.put(oid(1), b"a").unwrap();
+      let length = fs::metadata(&path).unwrap().len();
+      let mut data = fs::read(&path).unwrap();
+      write_record(&mut data, &oid(2), 9, SystemTime::now());
+      data.extend_from_slice(b"abc");
+      fs::write(&path, &data).unwrap();
+
+      let mut reopened = SingleFileStorage::new(&path, ObjectFormat::Sha1).unwrap();
+      assert_eq!(reopened.oids(), Ok(vec![oid(1)]));
+      assert_eq!(fs::metadata(&path).unwrap().len(), length);
+
+      reopened.put(oid(3), b"c").unwrap();
+      let reopened = SingleFileStorage::new(&path, ObjectFormat::Sha1).unwrap();
+      assert_eq!(reopened.oids(), Ok(vec![oid(1), oid(3)]));
+      assert_eq!(reopened.get(&oid(3)), Ok(Some(b"c".to_vec())));
+
+      let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_new_when_header_is_truncated() {
+      let path = temp_dir("truncated-header").join("objects.bin");
+      fs::write(&path, b"CKSF\0\0").unwrap();
+      let mut actual = SingleFileStorage::new(&path, ObjectFormat::Sha1).unwrap();
+
+      assert_eq!(actual.oids(), Ok(Vec::new()));
+      assert_eq!(fs::metadata(&path).unwrap().len(), 0);
+
+      actual.put(oid(1), b"a").unwrap();
+      let reopened = SingleFileStorage::new(&path, ObjectFormat::Sha1).unwrap();
+      assert_eq!(reopened.get(&oid(1)), Ok(Some(b"a".to_vec())));
+
+      fs::write(&path, b"CKSX").unwrap();
+      assert_eq!(
+        SingleFileStorage::new(&path, ObjectFormat::Sha1).err(),
+        Some(Error::StorageFailed {
+          detail: "truncated file header".to_owned()
+        })
+      );
+      assert_eq!(fs::read(&path).unwrap(), b"CKSX".to_vec());
+
+      let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_new_when_record_is_corrupt() {
+      let path = temp_dir("corrupt-record").join("objects.bin");
+      let mut storage = SingleFileStorage::new(&path, ObjectFormat::Sha1).unwrap();
+      storage.put(oid(1), b"a").unwrap();
+      storage.put(oid(2), b"b").unwrap();
+      storage.put(oid(3), b"c").unwrap();
+      let mut data = fs::read(&path).unwrap();
+      // The length of the second record.
+      let offset = FILE_HEADER_LENGTH as usize + 20 + RECORD_FIELDS_LENGTH + 1;
+      data[offset + 20 + 8 + 7] = 100;
+      fs::write(&path, &data).unwrap();
+
+      assert_eq!(
+        SingleFileStorage::new(&path, ObjectFormat::Sha1).err(),
+        Some(Error::StorageFailed {
+          detail: format!("corrupt record at offset {}", offset)
+        })
+      );
+      assert_eq!(fs::read(&path).unwrap(), data);
+
+      let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_new_when_file_header_is_invalid() {
+      let path = temp_dir("invalid-header").join("objects.bin");
+      let mut storage = SingleFileStorage::new(&path, ObjectFormat::Sha1).unwrap();
+      storage.put(oid(1), b"a").unwrap();
+
+      assert_eq!(
+        SingleFileStorage::new(&path, ObjectFormat::Sha256).err(),
+        Some(Error::ObjectFormatMismatch {
+          expected: "sha256".to_owned(),
+          actual: "sha1".to_owned(),
+        })
+      );
+
+      let mut data = fs::read(&path).unwrap();
+      data[7] = 2;
+      fs::write(&path, &data).unwrap();
+      assert_eq!(
+        SingleFileStorage::new(&path, ObjectFormat::Sha1).err(),
+        Some(Error::StorageFailed {
+          detail: "unsupported version: 2".to_owned()
+        })
+      );
+
+      fs::write(&path, b"not a storage file").unwrap();
+      assert_eq!(
+        SingleFileStorage::new(&path, ObjectFormat::Sha1).err(),
+        Some(Error::StorageFailed {
+          detail: "invalid signature".to_owned()
+        })
+      );
+
+      let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_put_refreshes_stored_at() {
+      let path = temp_dir("refresh").join("objects.bin");
+      let mut storage = SingleFileStorage::new(&path, ObjectFormat::Sha1).unwrap();
+      storage.put(oid(1), b"a").unwrap();
+      // Backdates the record to the epoch.
+      let mut data = fs::read(&path).unwrap();
+      let time = FILE_HEADER_LENGTH as usize + 20;
+      data[time..time + 8].copy_from_slice(&[0; 8]);
+      fs::write(&path, &data).unwrap();
+
+      let mut storage = SingleFileStorage::new(&path, ObjectFormat::Sha1).unwrap();
+      assert_eq!(storage.stored_at(&oid(1)), Ok(Some(UNIX_EPOCH)));
+      storage.put(oid(1), b"ignored").unwrap();
+      assert_ne!(storage.stored_at(&oid(1)), Ok(Some(UNIX_EPOCH)));
+
+      let reopened = SingleFileStorage::new(&path, ObjectFormat::Sha1).unwrap();
+      assert_ne!(reopened.stored_at(&oid(1)), Ok(Some(UNIX_EPOCH)));
+      assert_eq!(reopened.get(&oid(1)), Ok(Some(b"a".to_vec())));
+      assert_eq!(fs::metadata(&path).unwrap().len(), data.len() as u64);
+
+      let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_compact() {
+      let path = temp_dir("compact").join("objects.bin");
+      let mut storage = SingleFileStorage::new(&path, ObjectFormat::Sha1).unwrap();
+      storage.put(oid(1), b"first").unwrap();
+      storage.put(oid(2), b"second").unwrap();
+      storage.put(oid(3), b"third").unwrap();
+      storage.remove(&oid(2)).unwrap();
+      // Times are kept in whole seconds on disk.
+      let stored_at = SingleFileStorage::new(&path, ObjectFormat::Sha1)
+        .unwrap()
+        .stored_at(&oid(3))
+        .unwrap();
+      let length = fs::metadata(&path).unwrap().len();
+
+      storage.compact().unwrap();
+      assert!(fs::metadata(&path).unwrap().len() < length);
+      assert_eq!(storage.get(&oid(3)), Ok(Some(b"third".to_vec())));
+
+      storage.put(oid(4), b"fourth").unwrap();
+      let reopened = SingleFileStorage::new(&path, ObjectFormat::Sha1).unwrap();
+      assert_eq!(reopened.oids(), Ok(vec![oid(1), oid(3), oid(4)]));
+      assert_eq!(reopened.get(&oid(1)), Ok(Some(b"first".to_vec())));
+      assert_eq!(reopened.get(&oid(4)), Ok(Some(b"fourth".to_vec())));
+      assert_eq!(reopened.stored_at(&oid(3)), Ok(stored_at));
+
+      storage.remove(&oid(1)).unwrap();
+      storage.remove(&oid(3)).unwrap();
+      storage.remove(&oid(4)).unwrap();
+      storage.compact().unwrap();
+      assert_eq!(fs::metadata(&path).unwrap().len(), FILE_HEADER_LENGTH);
+      let reopened = SingleFileStorage::new(&path, ObjectFormat::Sha1).unwrap();
+      assert_eq!(reopened.oids(), Ok(Vec::new()));
+
+      let _ = fs::remove_file(&path);
+    }
+  }
+}
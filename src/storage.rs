@@ -0,0 +1,138 @@
+use super::error::Error;
+use super::object_format::ObjectId;
+use std::collections::BTreeMap;
+use std::io;
+use std::time::SystemTime;
+
+// Backends store zlib-compressed loose object bytes keyed by oid and never
+// look inside them, so every backend holds the same representation. They must
+// be shareable across threads because bundles are.
+pub trait Storage: Send + Sync {
+  fn get(&self, oid: &ObjectId) -> Result<Option<Vec<u8>>, Error>;
+  // Putting an oid that is already stored keeps its data and only refreshes
+  // the time it was stored at.
+  fn put(&mut self, oid: ObjectId, data: &[u8]) -> Result<(), Error>;
+  fn remove(&mut self, oid: &ObjectId) -> Result<bool, Error>;
+  fn contains(&self, oid: &ObjectId) -> Result<bool, Error>;
+  fn oids(&self) -> Result<Vec<ObjectId>, Error>;
+  // Stored length of the compressed bytes, without reading them.
+  fn size(&self, oid: &ObjectId) -> Result<Option<u64>, Error>;
+  // When the object was written, used to spare fresh objects from GC.
+  fn stored_at(&self, oid: &ObjectId) -> Result<Option<SystemTime>, Error>;
+  // Reclaims the space of removed objects, for backends that only mark them.
+  fn compact(&mut self) -> Result<(), Error> {
+    Ok(())
+  }
+}
+
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+  objects: BTreeMap<ObjectId, (Vec<u8>, SystemTime)>,
+}
+
+impl MemoryStorage {
+  pub fn new() -> MemoryStorage {
+    MemoryStorage {
+      objects: BTreeMap::new(),
+    }
+  }
+}
+
+impl Storage for MemoryStorage {
+  fn get(&self, oid: &ObjectId) -> Result<Option<Vec<u8>>, Error> {
+    Ok(self.objects.get(oid).map(|(data, _)| data.clone()))
+  }
+
+  fn put(&mut self, oid: ObjectId, data: &[u8]) -> Result<(), Error> {
+    let now = SystemTime::now();
+    self
+      .objects
+      .entry(oid)
+      .and_modify(|(_, time)| *time = now)
+      .or_insert_with(|| (data.to_vec(), now));
+
+    Ok(())
+  }
+
+  fn remove(&mut self, oid: &ObjectId) -> Result<bool, Error> {
+    Ok(self.objects.remove(oid).is_some())
+  }
+
+  fn contains(&self, oid: &ObjectId) -> Result<bool, Error> {
+    Ok(self.objects.contains_key(oid))
+  }
+
+  fn oids(&self) -> Result<Vec<ObjectId>, Error> {
+    Ok(self.objects.keys().cloned().collect())
+  }
+
+  fn size(&self, oid: &ObjectId) -> Result<Option<u64>, Error> {
+    Ok(self.objects.get(oid).map(|(data, _)| data.len() as u64))
+  }
+
+  fn stored_at(&self, oid: &ObjectId) -> Result<Option<SystemTime>, Error> {
+    Ok(self.objects.get(oid).map(|(_, time)| *time))
+  }
+}
+
+pub(crate) fn io_result<T>(result: io::Result<T>) -> Result<T, Error> {
+  match result {
+    Ok(v) => Ok(v),
+    Err(err) => Err(Error::StorageFailed {
+      detail: err.to_string(),
+    }),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::test_helper::oid;
+
+  mod memory_storage {
+    use super::*;
+
+    #[test]
+    fn test_put_and_get() {
+      let mut storage = MemoryStorage::new();
+      storage.put(oid(1), b"data").unwrap();
+
+      assert_eq!(storage.get(&oid(1)), Ok(Some(b"data".to_vec())));
+      assert_eq!(storage.get(&oid(2)), Ok(None));
+      assert_eq!(storage.contains(&oid(1)), Ok(true));
+      assert_eq!(storage.contains(&oid(2)), Ok(false));
+      assert_eq!(storage.size(&oid(1)), Ok(Some(4)));
+      assert_eq!(storage.size(&oid(2)), Ok(None));
+    }
+
+    #[test]
+    fn test_put_keeps_existing_data() {
+      let mut storage = MemoryStorage::new();
+      storage.put(oid(1), b"first").unwrap();
+      storage.put(oid(1), b"second").unwrap();
+
+      assert_eq!(storage.get(&oid(1)), Ok(Some(b"first".to_vec())));
+    }
+
+    #[test]
+    fn test_remove() {
+      let mut storage = MemoryStorage::new();
+      storage.put(oid(1), b"a").unwrap();
+
+      assert!(storage.stored_at(&oid(1)).unwrap().is_some());
+      assert_eq!(storage.remove(&oid(1)), Ok(true));
+      assert_eq!(storage.remove(&oid(1)), Ok(false));
+      assert_eq!(storage.contains(&oid(1)), Ok(false));
+      assert_eq!(storage.stored_at(&oid(1)), Ok(None));
+    }
+
+    #[test]
+    fn test_oids() {
+      let mut storage = MemoryStorage::new();
+      storage.put(oid(2), b"b").unwrap();
+      storage.put(oid(1), b"a").unwrap();
+
+      assert_eq!(storage.oids(), Ok(vec![oid(1), oid(2)]));
+    }
+  }
+}
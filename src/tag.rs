@@ -0,0 +1,375 @@
+use super::compressed_git_object::{to_object_type, CompressedGitObject};
+use super::error::Error;
+use super::git_object::GitObject;
+use super::object_format::{ObjectFormat, ObjectId};
+use super::object_header::{self, Header};
+use super::parse_mode::ParseMode;
+use super::signature::{signature_bytes, Signature};
+use git2::ObjectType;
+
+const SIGNATURE_MARKERS: [&[u8]; 4] = [
+  b"-----BEGIN PGP SIGNATURE-----",
+  b"-----BEGIN PGP MESSAGE-----",
+  b"-----BEGIN SSH SIGNATURE-----",
+  b"-----BEGIN SIGNED MESSAGE-----",
+];
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Tag {
+  pub object: ObjectId,
+  pub target_type: ObjectType,
+  pub tag: Vec<u8>,
+  pub tagger: Option<Signature>,
+  pub extra_headers: Vec<Header>,
+  pub message: Vec<u8>,
+  pub signature: Option<Vec<u8>>,
+  // The tagger as it was parsed, written back unchanged. Lenient parsing
+  // keeps it here even when it is not a valid signature.
+  raw_tagger: Option<Vec<u8>>,
+}
+
+impl Tag {
+  pub fn new(
+    object: ObjectId,
+    target_type: ObjectType,
+    tag: &[u8],
+    tagger: Signature,
+    message: &[u8],
+  ) -> Tag {
+    Tag {
+      object,
+      target_type,
+      tag: tag.to_vec(),
+      tagger: Some(tagger),
+      extra_headers: Vec::new(),
+      message: message.to_vec(),
+      signature: None,
+      raw_tagger: None,
+    }
+  }
+
+  pub fn from_u8_vec(data: &Vec<u8>, mode: ParseMode) -> Result<Tag, Error> {
+    Tag::from_u8_vec_with_format(data, mode, ObjectFormat::Sha1)
+  }
+
+  pub fn from_u8_vec_with_format(
+    data: &Vec<u8>,
+    mode: ParseMode,
+    format: ObjectFormat,
+  ) -> Result<Tag, Error> {
+    let compressed = CompressedGitObject::new(data);
+    let (object_type, _, content) = compressed.parse()?;
+    expect_tag(object_type)?;
+
+    Tag::parse_with_format(&content, mode, format)
+  }
+
+  pub fn from_git_object(object: &GitObject, mode: ParseMode) -> Result<Tag, Error> {
+    Tag::from_git_object_with_format(object, mode, ObjectFormat::Sha1)
+  }
+
+  pub fn from_git_object_with_format(
+    object: &GitObject,
+    mode: ParseMode,
+    format: ObjectFormat,
+  ) -> Result<Tag, Error> {
+    expect_tag(object.object_type())?;
+
+    Tag::parse_with_format(object.content(), mode, format)
+  }
+
+  pub fn parse(content: &[u8], mode: ParseMode) -> Result<Tag, Error> {
+    Tag::parse_with_format(content, mode, ObjectFormat::Sha1)
+  }
+
+  pub fn parse_with_format(
+    content: &[u8],
+    mode: ParseMode,
+    format: ObjectFormat,
+  ) -> Result<Tag, Error> {
+    let (section, body) = match (object_header::split_body(content), mode) {
+      (Some(v), _) => Ok(v),
+      (None, ParseMode::Lenient) => Ok((content, &b""[..])),
+      (None, ParseMode::Strict) => Err(malformed("message separator not found")),
+    }?;
+    let headers = match object_header::parse_headers(section) {
+      Some(v) => Ok(v),
+      None => Err(malformed("invalid header line")),
+    }?;
+    let mut headers = headers.into_iter().peekable();
+
+    let object = match headers.next() {
+      Some((ref key, ref value)) if key == "object" => parse_oid(value, format),
+      _ => Err(malformed("object not found")),
+    }?;
+    let target_type = match headers.next() {
+      Some((ref key, ref value)) if key == "type" => parse_type(value),
+      _ => Err(malformed("type not found")),
+    }?;
+    let tag = match headers.next() {
+      Some((ref key, ref value)) if key == "tag" && !value.is_empty() => Ok(value.to_owned()),
+      _ => Err(malformed("tag not found")),
+    }?;
+
+    let raw_tagger = match headers.peek() {
+      Some((key, _)) if key == "tagger" => headers.next().map(|(_, value)| value),
+      _ => None,
+    };
+    let tagger = match (&raw_tagger, mode) {
+      (Some(v), ParseMode::Strict) => Some(Signature::parse(v)?),
+      (Some(v), ParseMode::Lenient) => Signature::parse(v).ok(),
+      (None, ParseMode::Strict) => return Err(malformed("tagger not found")),
+      (None, ParseMode::Lenient) => None,
+    };
+
+    let extra_headers: Vec<Header> = headers.collect();
+    if let (Some((key, _)), ParseMode::Strict) = (extra_headers.first(), mode) {
+      return Err(malformed(&format!("unexpected header {}", key)));
+    }
+
+    let (message, signature) = split_signature(body);
+
+    Ok(Tag {
+      object,
+      target_type,
+      tag,
+      tagger,
+      extra_headers,
+      message: message.to_vec(),
+      signature: signature.map(|v| v.to_vec()),
+      raw_tagger,
+    })
+  }
+
+  pub fn to_bytes(&self) -> Vec<u8> {
+    let mut ret: Vec<u8> = Vec::new();
+    object_header::write_header(&mut ret, "object", self.object.to_string().as_bytes());
+    object_header::write_header(&mut ret, "type", self.target_type.str().as_bytes());
+    object_header::write_header(&mut ret, "tag", &self.tag);
+    let tagger = match (&self.tagger, &self.raw_tagger) {
+      (Some(v), raw) => Some(signature_bytes(v, raw)),
+      (None, Some(raw)) if Signature::parse(raw).is_err() => Some(raw.clone()),
+      (None, _) => None,
+    };
+    if let Some(tagger) = tagger {
+      object_header::write_header(&mut ret, "tagger", &tagger);
+    }
+    for (key, value) in &self.extra_headers {
+      object_header::write_header(&mut ret, key, value);
+    }
+    ret.push(b'\n');
+    ret.extend_from_slice(&self.message);
+    if let Some(signature) = &self.signature {
+      ret.extend_from_slice(signature);
+    }
+
+    ret
+  }
+
+  pub fn to_git_object(&self) -> GitObject {
+    let content = self.to_bytes();
+
+    GitObject::new(ObjectType::Tag, content.len() as u64, &content)
+  }
+}
+
+fn expect_tag(object_type: ObjectType) -> Result<(), Error> {
+  match object_type {
+    ObjectType::Tag => Ok(()),
+    actual => Err(Error::ObjectTypeMismatch {
+      expected: ObjectType::Tag.to_string(),
+      actual: actual.to_string(),
+    }),
+  }
+}
+
+fn parse_oid(value: &[u8], format: ObjectFormat) -> Result<ObjectId, Error> {
+  match object_header::parse_hex_oid(value, format) {
+    Some(v) => Ok(v),
+    None => Err(Error::InvalidObjectId(
+      String::from_utf8_lossy(value).into_owned(),
+    )),
+  }
+}
+
+fn parse_type(value: &[u8]) -> Result<ObjectType, Error> {
+  match std::str::from_utf8(value) {
+    Ok(v) => to_object_type(v),
+    Err(_) => Err(Error::InvalidTypeName),
+  }
+}
+
+fn split_signature(body: &[u8]) -> (&[u8], Option<&[u8]>) {
+  let mut found: Option<usize> = None;
+  let mut pos = 0;
+
+  while pos < body.len() {
+    let line = &body[pos..];
+    if SIGNATURE_MARKERS.iter().any(|v| line.starts_with(v)) {
+      found = Some(pos);
+    }
+
+    pos += match line.iter().position(|&v| v == b'\n') {
+      Some(v) => v + 1,
+      None => line.len(),
+    };
+  }
+
+  match found {
+    Some(pos) => (&body[..pos], Some(&body[pos..])),
+    None => (body, None),
+  }
+}
+
+fn malformed(reason: &str) -> Error {
+  Error::MalformedTag(reason.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const SIGNED_TAG: &[u8] = b"object 0123456789abcdef0123456789abcdef01234567\n\
+type commit\n\
+tag v0.0.1\n\
+tagger tett23 <tett23@example.com> 1549870000 +0900\n\
+\n\
+Release v0.0.1\n\
+-----BEGIN PGP SIGNATURE-----\n\
+\n\
+iQEzBAABCAAdFiEE\n\
+-----END PGP SIGNATURE-----\n";
+
+  const LEGACY_TAG: &[u8] = b"object 0123456789abcdef0123456789abcdef01234567\n\
+type commit\n\
+tag v0.0.0\n\
+\n\
+Tag without tagger\n";
+
+  mod tag {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+      let actual = Tag::parse(SIGNED_TAG, ParseMode::Strict).unwrap();
+
+      assert_eq!(
+        actual.object.to_string(),
+        "0123456789abcdef0123456789abcdef01234567"
+      );
+      assert_eq!(actual.target_type, ObjectType::Commit);
+      assert_eq!(actual.tag, b"v0.0.1".to_vec());
+      assert_eq!(
+        actual.tagger,
+        Some(Signature::new(
+          b"tett23",
+          b"tett23@example.com",
+          1549870000,
+          540
+        ))
+      );
+      assert_eq!(actual.message, b"Release v0.0.1\n".to_vec());
+      assert_eq!(
+        actual.signature,
+        Some(
+          b"-----BEGIN PGP SIGNATURE-----\n\niQEzBAABCAAdFiEE\n-----END PGP SIGNATURE-----\n"
+            .to_vec()
+        )
+      );
+    }
+
+    #[test]
+    fn test_to_bytes_round_trip() {
+      let actual = Tag::parse(SIGNED_TAG, ParseMode::Strict)
+        .unwrap()
+        .to_bytes();
+
+      assert_eq!(actual, SIGNED_TAG.to_vec());
+    }
+
+    #[test]
+    fn test_parse_without_tagger() {
+      let strict = Tag::parse(LEGACY_TAG, ParseMode::Strict);
+      let lenient = Tag::parse(LEGACY_TAG, ParseMode::Lenient).unwrap();
+
+      assert_eq!(
+        strict,
+        Err(Error::MalformedTag("tagger not found".to_owned()))
+      );
+      assert_eq!(lenient.tagger, None);
+      assert_eq!(lenient.to_bytes(), LEGACY_TAG.to_vec());
+    }
+
+    #[test]
+    fn test_parse_when_tagger_is_malformed() {
+      let content = b"object 0123456789abcdef0123456789abcdef01234567\n\
+type commit\n\
+tag v1\n\
+tagger broken\n\
+\n\
+message\n";
+      let strict = Tag::parse(content, ParseMode::Strict);
+      let lenient = Tag::parse(content, ParseMode::Lenient).unwrap();
+
+      assert_eq!(strict, Err(Error::MalformedSignature("broken".to_owned())));
+      assert_eq!(lenient.tagger, None);
+      assert_eq!(lenient.to_bytes(), content.to_vec());
+    }
+
+    #[test]
+    fn test_parse_with_unknown_header() {
+      let content = b"object 0123456789abcdef0123456789abcdef01234567\n\
+type blob\n\
+tag v1\n\
+tagger a <a@example.com> 0 +0000\n\
+x-custom value\n\
+\n\
+message\n";
+      let strict = Tag::parse(content, ParseMode::Strict);
+      let lenient = Tag::parse(content, ParseMode::Lenient).unwrap();
+
+      assert!(strict.is_err());
+      assert_eq!(
+        lenient.extra_headers,
+        vec![("x-custom".to_owned(), b"value".to_vec())]
+      );
+      assert_eq!(lenient.to_bytes(), content.to_vec());
+    }
+
+    #[test]
+    fn test_parse_with_sha256_format() {
+      let object = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+      let content = format!(
+        "object {}\ntype commit\ntag v1\ntagger a <a@example.com> 0 +0000\n\nmessage\n",
+        object
+      );
+      let actual =
+        Tag::parse_with_format(content.as_bytes(), ParseMode::Strict, ObjectFormat::Sha256)
+          .unwrap();
+
+      assert_eq!(actual.object.to_string(), object);
+      assert_eq!(actual.to_bytes(), content.as_bytes().to_vec());
+      assert!(Tag::parse(content.as_bytes(), ParseMode::Strict).is_err());
+    }
+
+    #[test]
+    fn test_parse_when_type_is_invalid() {
+      let content = b"object 0123456789abcdef0123456789abcdef01234567\n\
+type branch\n\
+tag v1\n\
+tagger a <a@example.com> 0 +0000\n\
+\n";
+      let actual = Tag::parse(content, ParseMode::Lenient);
+
+      assert_eq!(actual, Err(Error::InvalidTypeName));
+    }
+
+    #[test]
+    fn test_from_git_object() {
+      let tag = Tag::parse(SIGNED_TAG, ParseMode::Strict).unwrap();
+      let actual = Tag::from_git_object(&tag.to_git_object(), ParseMode::Strict);
+
+      assert_eq!(actual, Ok(tag));
+    }
+  }
+}
@@ -0,0 +1,90 @@
+// Fixtures shared by the unit tests of every module.
+use super::bundled_repository::BundledRepository;
+use super::git_object::GitObject;
+use super::object_format::{ObjectFormat, ObjectId};
+use super::path_fragment::PathFragment;
+use super::tree_object::FileMode;
+use git2::ObjectType;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static TEMP_DIRS: AtomicUsize = AtomicUsize::new(0);
+
+// Returns a fresh, empty directory. Tests run in parallel within one process,
+// so every call gets its own directory even when `name` repeats.
+pub fn temp_dir(name: &str) -> PathBuf {
+  let path = std::env::temp_dir().join(format!(
+    "ckusro-core-{}-{}-{}",
+    std::process::id(),
+    TEMP_DIRS.fetch_add(1, Ordering::SeqCst),
+    name
+  ));
+  let _ = fs::remove_dir_all(&path);
+  fs::create_dir_all(&path).unwrap();
+
+  path
+}
+
+// Runs git in `dir` with a fixed identity and returns its stdout.
+pub fn git(dir: &Path, args: &[&str]) -> String {
+  let output = Command::new("git")
+    .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+    .args(args)
+    .current_dir(dir)
+    .stdin(Stdio::null())
+    .output()
+    .unwrap();
+  assert!(
+    output.status.success(),
+    "git {:?} failed: {}",
+    args,
+    String::from_utf8_lossy(&output.stderr)
+  );
+
+  String::from_utf8(output.stdout).unwrap()
+}
+
+pub fn commit_file(dir: &Path, name: &str, content: &str) {
+  fs::write(dir.join(name), content).unwrap();
+  git(dir, &["add", "."]);
+  git(dir, &["commit", "-q", "-m", name]);
+}
+
+pub fn blob(content: &[u8]) -> GitObject {
+  GitObject::new(ObjectType::Blob, content.len() as u64, &content.to_vec())
+}
+
+// A tree of regular files, in the order given.
+pub fn tree(entries: &[(&str, ObjectId)]) -> GitObject {
+  let mut content = Vec::new();
+  for (name, oid) in entries {
+    content.extend(format!("{} {}\0", FileMode::Blob.as_str(), name).into_bytes());
+    content.extend_from_slice(oid.as_bytes());
+  }
+
+  GitObject::new(ObjectType::Tree, content.len() as u64, &content)
+}
+
+// The SHA-1 id of `object` and the compressed bytes a storage keeps for it.
+pub fn loose(object: &GitObject) -> (ObjectId, Vec<u8>) {
+  (
+    object.object_id(ObjectFormat::Sha1),
+    object.to_u8_vec().unwrap(),
+  )
+}
+
+// Points `refs/heads/master` of `fragment` at `oid`.
+pub fn set_root(repository: &mut BundledRepository, fragment: &str, oid: ObjectId) {
+  let fragment = PathFragment::parse_full_qualified_fragment(fragment).unwrap();
+  let mut refs = BTreeMap::new();
+  refs.insert("refs/heads/master".to_owned(), oid);
+  repository.set_refs(&fragment, refs).unwrap();
+}
+
+// A SHA-1 id made of `byte` repeated, for tests that never look the object up.
+pub fn oid(byte: u8) -> ObjectId {
+  ObjectId::from_bytes(ObjectFormat::Sha1, &[byte; 20]).unwrap()
+}
@@ -0,0 +1,329 @@
+use super::compressed_git_object::CompressedGitObject;
+use super::error::Error;
+use super::git_object::GitObject;
+use super::object_format::{ObjectFormat, ObjectId};
+use git2::ObjectType;
+use std::cmp::Ordering;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum FileMode {
+  Tree,
+  Blob,
+  BlobExecutable,
+  Link,
+  Commit,
+}
+
+impl FileMode {
+  pub fn from_bytes(mode: &[u8]) -> Result<FileMode, Error> {
+    match mode {
+      b"40000" => Ok(FileMode::Tree),
+      b"100644" => Ok(FileMode::Blob),
+      b"100755" => Ok(FileMode::BlobExecutable),
+      b"120000" => Ok(FileMode::Link),
+      b"160000" => Ok(FileMode::Commit),
+      _ => Err(Error::InvalidFileMode(
+        String::from_utf8_lossy(mode).into_owned(),
+      )),
+    }
+  }
+
+  pub fn as_str(self) -> &'static str {
+    match self {
+      FileMode::Tree => "40000",
+      FileMode::Blob => "100644",
+      FileMode::BlobExecutable => "100755",
+      FileMode::Link => "120000",
+      FileMode::Commit => "160000",
+    }
+  }
+
+  pub fn object_type(self) -> ObjectType {
+    match self {
+      FileMode::Tree => ObjectType::Tree,
+      FileMode::Blob | FileMode::BlobExecutable | FileMode::Link => ObjectType::Blob,
+      FileMode::Commit => ObjectType::Commit,
+    }
+  }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct TreeEntry {
+  pub mode: FileMode,
+  pub name: Vec<u8>,
+  pub oid: ObjectId,
+}
+
+impl TreeEntry {
+  pub fn new(mode: FileMode, name: &[u8], oid: ObjectId) -> TreeEntry {
+    TreeEntry {
+      mode,
+      name: name.to_vec(),
+      oid,
+    }
+  }
+
+  pub fn kind(&self) -> ObjectType {
+    self.mode.object_type()
+  }
+
+  // Git sorts tree entries as if the names of subtrees ended with '/'.
+  fn sort_key_at(&self, pos: usize) -> Option<u8> {
+    match self.name.get(pos) {
+      Some(v) => Some(*v),
+      None if pos == self.name.len() && self.mode == FileMode::Tree => Some(b'/'),
+      None => None,
+    }
+  }
+
+  fn compare(&self, other: &TreeEntry) -> Ordering {
+    let length = self.name.len().max(other.name.len()) + 1;
+    for pos in 0..length {
+      match self.sort_key_at(pos).cmp(&other.sort_key_at(pos)) {
+        Ordering::Equal => continue,
+        ordering => return ordering,
+      }
+    }
+
+    Ordering::Equal
+  }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct TreeObject {
+  entries: Vec<TreeEntry>,
+}
+
+impl TreeObject {
+  pub fn new(entries: Vec<TreeEntry>) -> TreeObject {
+    TreeObject { entries }
+  }
+
+  pub fn entries(&self) -> &Vec<TreeEntry> {
+    &self.entries
+  }
+
+  pub fn from_u8_vec(data: &Vec<u8>) -> Result<TreeObject, Error> {
+    TreeObject::from_u8_vec_with_format(data, ObjectFormat::Sha1)
+  }
+
+  pub fn from_u8_vec_with_format(
+    data: &Vec<u8>,
+    format: ObjectFormat,
+  ) -> Result<TreeObject, Error> {
+    let compressed = CompressedGitObject::new(data);
+    let (object_type, _, content) = compressed.parse()?;
+    expect_tree(object_type)?;
+
+    TreeObject::parse_with_format(&content, format)
+  }
+
+  pub fn from_git_object(object: &GitObject) -> Result<TreeObject, Error> {
+    TreeObject::from_git_object_with_format(object, ObjectFormat::Sha1)
+  }
+
+  pub fn from_git_object_with_format(
+    object: &GitObject,
+    format: ObjectFormat,
+  ) -> Result<TreeObject, Error> {
+    expect_tree(object.object_type())?;
+
+    TreeObject::parse_with_format(object.content(), format)
+  }
+
+  pub fn parse(content: &[u8]) -> Result<TreeObject, Error> {
+    TreeObject::parse_with_format(content, ObjectFormat::Sha1)
+  }
+
+  pub fn parse_with_format(content: &[u8], format: ObjectFormat) -> Result<TreeObject, Error> {
+    let mut entries: Vec<TreeEntry> = Vec::new();
+    let mut pos = 0;
+
+    while pos < content.len() {
+      let (entry, next) = parse_entry(content, pos, format)?;
+      if let Some(previous) = entries.last() {
+        if previous.compare(&entry) != Ordering::Less {
+          return Err(Error::UnsortedTreeEntries(
+            String::from_utf8_lossy(&entry.name).into_owned(),
+          ));
+        }
+      }
+
+      entries.push(entry);
+      pos = next;
+    }
+
+    Ok(TreeObject::new(entries))
+  }
+}
+
+fn expect_tree(object_type: ObjectType) -> Result<(), Error> {
+  match object_type {
+    ObjectType::Tree => Ok(()),
+    actual => Err(Error::ObjectTypeMismatch {
+      expected: ObjectType::Tree.to_string(),
+      actual: actual.to_string(),
+    }),
+  }
+}
+
+fn parse_entry(
+  content: &[u8],
+  offset: usize,
+  format: ObjectFormat,
+) -> Result<(TreeEntry, usize), Error> {
+  let rest = &content[offset..];
+
+  let mode_end = match rest.iter().position(|&v| v == b' ') {
+    Some(v) => Ok(v),
+    None => Err(Error::TruncatedTreeEntry(offset)),
+  }?;
+  let mode = FileMode::from_bytes(&rest[..mode_end])?;
+
+  let name_start = mode_end + 1;
+  let name_end = match rest[name_start..].iter().position(|&v| v == 0) {
+    Some(v) => Ok(name_start + v),
+    None => Err(Error::TruncatedTreeEntry(offset)),
+  }?;
+  let name = &rest[name_start..name_end];
+
+  let oid_start = name_end + 1;
+  let oid_end = oid_start + format.id_length();
+  if rest.len() < oid_end {
+    return Err(Error::TruncatedTreeEntry(offset));
+  }
+  let oid = ObjectId::from_bytes(format, &rest[oid_start..oid_end])?;
+
+  Ok((TreeEntry::new(mode, name, oid), offset + oid_end))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use flate2::write::ZlibEncoder;
+  use flate2::Compression;
+  use git2::Oid;
+  use std::io::prelude::*;
+
+  fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data).unwrap();
+
+    encoder.finish().unwrap()
+  }
+
+  fn entry_bytes(mode: &str, name: &str, oid: &[u8]) -> Vec<u8> {
+    let mut ret = format!("{} {}\0", mode, name).into_bytes();
+    ret.extend_from_slice(oid);
+
+    ret
+  }
+
+  mod tree_object {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+      let mut content = entry_bytes("100644", "README.md", b"12345678901234567890");
+      content.extend(entry_bytes("40000", "src", b"abcdefghijabcdefghij"));
+      let actual = TreeObject::parse(&content).unwrap();
+      let expected = TreeObject::new(vec![
+        TreeEntry::new(
+          FileMode::Blob,
+          b"README.md",
+          Oid::from_bytes(b"12345678901234567890").unwrap().into(),
+        ),
+        TreeEntry::new(
+          FileMode::Tree,
+          b"src",
+          Oid::from_bytes(b"abcdefghijabcdefghij").unwrap().into(),
+        ),
+      ]);
+
+      assert_eq!(actual, expected);
+      assert_eq!(actual.entries()[0].kind(), ObjectType::Blob);
+      assert_eq!(actual.entries()[1].kind(), ObjectType::Tree);
+    }
+
+    #[test]
+    fn test_parse_with_sha256_format() {
+      let oid = b"0123456789abcdef0123456789abcdef";
+      let content = entry_bytes("100755", "run.sh", oid);
+      let actual = TreeObject::parse_with_format(&content, ObjectFormat::Sha256).unwrap();
+      let expected = TreeObject::new(vec![TreeEntry::new(
+        FileMode::BlobExecutable,
+        b"run.sh",
+        ObjectId::from_bytes(ObjectFormat::Sha256, oid).unwrap(),
+      )]);
+
+      assert_eq!(actual, expected);
+      assert!(TreeObject::parse(&content).is_err());
+    }
+
+    #[test]
+    fn test_parse_empty_tree() {
+      let actual = TreeObject::parse(b"");
+
+      assert_eq!(actual, Ok(TreeObject::new(Vec::new())));
+    }
+
+    #[test]
+    fn test_parse_when_entry_is_truncated() {
+      let content = entry_bytes("100644", "a", b"1234567890");
+      let actual = TreeObject::parse(&content);
+
+      assert_eq!(actual, Err(Error::TruncatedTreeEntry(0)));
+    }
+
+    #[test]
+    fn test_parse_when_mode_is_invalid() {
+      let content = entry_bytes("100600", "a", b"12345678901234567890");
+      let actual = TreeObject::parse(&content);
+
+      assert_eq!(actual, Err(Error::InvalidFileMode("100600".to_owned())));
+    }
+
+    #[test]
+    fn test_parse_when_entries_are_not_sorted() {
+      let mut content = entry_bytes("100644", "b", b"12345678901234567890");
+      content.extend(entry_bytes("100644", "a", b"12345678901234567890"));
+      let actual = TreeObject::parse(&content);
+
+      assert_eq!(actual, Err(Error::UnsortedTreeEntries("a".to_owned())));
+    }
+
+    #[test]
+    fn test_parse_sorts_trees_with_trailing_slash() {
+      let mut content = entry_bytes("100644", "foo.txt", b"12345678901234567890");
+      content.extend(entry_bytes("40000", "foo", b"12345678901234567890"));
+      let actual = TreeObject::parse(&content);
+
+      assert!(actual.is_ok());
+    }
+
+    #[test]
+    fn test_from_u8_vec() {
+      let content = entry_bytes("120000", "link", b"12345678901234567890");
+      let mut data = format!("tree {}\0", content.len()).into_bytes();
+      data.extend(content);
+      let actual = TreeObject::from_u8_vec(&deflate(&data)).unwrap();
+
+      assert_eq!(actual.entries()[0].mode, FileMode::Link);
+      assert_eq!(actual.entries()[0].name, b"link".to_vec());
+    }
+
+    #[test]
+    fn test_from_git_object_when_object_is_not_tree() {
+      let object = GitObject::new(ObjectType::Blob, 1, &b"a".to_vec());
+      let actual = TreeObject::from_git_object(&object);
+
+      assert_eq!(
+        actual,
+        Err(Error::ObjectTypeMismatch {
+          expected: "tree".to_owned(),
+          actual: "blob".to_owned(),
+        })
+      );
+    }
+  }
+}